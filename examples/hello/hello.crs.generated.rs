@@ -1,10 +0,0 @@
-#![no_std]
-use core::ffi::CStr;
-use libc::printf;
-pub(self) unsafe fn foo(n: i32) -> i32 {
-    n * 2 * (libc::rand() as f32 / libc::RAND_MAX as f32)
-}
-pub unsafe fn main(_argv: *const [*const CStr]) -> i32 {
-    printf!("hello world\n");
-    0
-}
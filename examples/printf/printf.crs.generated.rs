@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+pub use libc::printf;
+// `print!` and `println!` become `printf!`, with conversions picked from the argument types.
+pub unsafe fn main() {
+    greet(3);
+    libc::printf!("x = 5, y = %.2f, s = hi, b = %s 100%%\n", 1.5f32 as libc ::
+    c_double, (if true { c"true" } else { c"false" }).as_ptr());
+    printf!("%s\n", c"done".as_ptr());
+}
+pub unsafe fn greet(n: i32) { printf!("hello %d\n", n); }
+#[export_name = "main"]
+pub unsafe extern "C" fn __crust_main(_argc: libc::c_int,
+    _argv: *mut *mut libc::c_char) -> libc::c_int {
+    main();
+    0
+}
+#[panic_handler]
+fn __crust_panic_handler(info: &core::panic::PanicInfo) -> ! {
+    unsafe {
+        if let Some(location) = info.location() {
+            let file = location.file();
+            libc::fprintf!(libc :: stderr, "panicked at %.*s:%u:%u:\n",
+            file.len() as libc :: c_int, file.as_ptr(), location.line(),
+            location.column());
+        } else { libc::fprintf!(libc :: stderr, "panicked\n"); }
+        if let Some(message) = info.message().as_str() {
+            libc::fprintf!(libc :: stderr, "%.*s\n", message.len() as libc ::
+            c_int, message.as_ptr());
+        }
+        libc::abort()
+    }
+}
+#[no_mangle]
+extern "C" fn rust_eh_personality() {}
\ No newline at end of file
@@ -1,6 +0,0 @@
-#![no_std]
-pub unsafe fn foo(n: !) {}
-pub unsafe fn foo_mut(n: !) {}
-pub unsafe fn foo_lt(m: !) {}
-pub unsafe fn foo_lt_mut(m: !) {}
-pub unsafe fn foo_box(n: Box) {}
@@ -1,5 +1,28 @@
 #![no_std]
+#![no_main]
 pub unsafe fn main() {}
-pub unsafe fn foo(n: i32) -> i32 {
-    n * n + 5
+pub unsafe fn foo(n: i32) -> i32 { n * n + 5 }
+#[export_name = "main"]
+pub unsafe extern "C" fn __crust_main(_argc: libc::c_int,
+    _argv: *mut *mut libc::c_char) -> libc::c_int {
+    main();
+    0
 }
+#[panic_handler]
+fn __crust_panic_handler(info: &core::panic::PanicInfo) -> ! {
+    unsafe {
+        if let Some(location) = info.location() {
+            let file = location.file();
+            libc::fprintf!(libc :: stderr, "panicked at %.*s:%u:%u:\n",
+            file.len() as libc :: c_int, file.as_ptr(), location.line(),
+            location.column());
+        } else { libc::fprintf!(libc :: stderr, "panicked\n"); }
+        if let Some(message) = info.message().as_str() {
+            libc::fprintf!(libc :: stderr, "%.*s\n", message.len() as libc ::
+            c_int, message.as_ptr());
+        }
+        libc::abort()
+    }
+}
+#[no_mangle]
+extern "C" fn rust_eh_personality() {}
\ No newline at end of file
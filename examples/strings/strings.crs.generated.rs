@@ -1,4 +0,0 @@
-#![no_std]
-pub unsafe fn main() {
-    ()
-}
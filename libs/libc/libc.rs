@@ -1,7 +1,9 @@
 #![no_std]
 
-pub use core::ffi::{c_void, c_char, c_int, c_long, c_float, c_double, CStr};
+pub use core::ffi::{c_void, c_char, c_int, c_uint, c_long, c_ulong, c_longlong, c_ulonglong, c_float, c_double, CStr};
 
+/// C's `printf`. The format is a plain string literal, which gets its NUL here, so a C
+/// string literal like `c"..."` isn't accepted.
 #[macro_export]
 macro_rules! printf {
     ($fmt:literal $($args:tt)*) => {{
@@ -10,7 +12,33 @@ macro_rules! printf {
             #[link_name = "printf"]
             pub fn printf_raw(fmt: *const c_char, ...) -> c_int;
         }
-        printf_raw($fmt.as_ptr() $($args)*)
+        printf_raw(concat!($fmt, "\0").as_ptr() as *const c_char $($args)*)
+    }};
+}
+
+/// C's `fprintf`, with a format like [`printf!`]'s.
+#[macro_export]
+macro_rules! fprintf {
+    ($stream:expr, $fmt:literal $($args:tt)*) => {{
+        use ::core::ffi::{c_char, c_int};
+        extern "C" {
+            #[link_name = "fprintf"]
+            pub fn fprintf_raw(stream: *mut $crate::FILE, fmt: *const c_char, ...) -> c_int;
+        }
+        fprintf_raw($stream, concat!($fmt, "\0").as_ptr() as *const c_char $($args)*)
+    }};
+}
+
+/// C's `snprintf`, with a format like [`printf!`]'s.
+#[macro_export]
+macro_rules! snprintf {
+    ($buf:expr, $size:expr, $fmt:literal $($args:tt)*) => {{
+        use ::core::ffi::{c_char, c_int};
+        extern "C" {
+            #[link_name = "snprintf"]
+            pub fn snprintf_raw(buf: *mut c_char, size: usize, fmt: *const c_char, ...) -> c_int;
+        }
+        snprintf_raw($buf, $size, concat!($fmt, "\0").as_ptr() as *const c_char $($args)*)
     }};
 }
 
@@ -19,6 +47,12 @@ pub type FILE = c_void;
 pub const SEEK_END: c_int = 2;
 
 extern "C" {
+    #[cfg_attr(target_os = "macos", link_name = "__stderrp")]
+    pub static mut stderr: *mut FILE;
+
+    #[link_name = "abort"]
+    fn abort_raw() -> !;
+
     #[link_name = "fopen"]
     fn fopen_raw(pathname: *const c_char, mode: *const c_char) -> *mut FILE;
 
@@ -41,6 +75,10 @@ extern "C" {
     fn calloc_raw(n: usize, size: usize) -> *mut c_void;
 }
 
+pub unsafe fn abort() -> ! {
+    abort_raw()
+}
+
 pub unsafe fn fopen(pathname: *const CStr, mode: *const CStr) -> *mut FILE {
     fopen_raw((*pathname).as_ptr(), (*mode).as_ptr())
 }
@@ -0,0 +1,230 @@
+//! C declarations for what a Crust library exports, for `--emit=c-header`.
+//!
+//! C can only link against what keeps its name, so the header declares the `#[no_mangle]`
+//! `extern "C"` functions and `#[no_mangle]` statics of the generated Rust, and turns its
+//! constants into macros. Everything is declared with C's own types: the header has no type
+//! definitions, because crustc rejects structs, enums and unions before it gets here. Defining
+//! the `#[repr(C)]` ones is left for when they're translated (see TODO.md).
+
+use std::path::Path;
+
+use quote::ToTokens;
+
+use crate::{
+    diagnostics::{Diagnostic, Diagnostics},
+    error_codes,
+    span_map::SourceSpans,
+};
+
+/// A Rust type as C spells it.
+enum CType {
+    Named(&'static str),
+    Pointer { pointee: Box<CType>, mutable: bool },
+}
+
+impl CType {
+    /// Declares `declarator` to be of this type. C declarations read inside out, so a
+    /// pointer wraps the declarator and hands it on to what it points to.
+    fn declare(&self, declarator: &str, is_const: bool) -> String {
+        let qualifier = if is_const { "const " } else { "" };
+        match self {
+            Self::Named(name) => format!("{qualifier}{name} {declarator}").trim_end().to_string(),
+            Self::Pointer { pointee, mutable } => pointee.declare(&format!("*{qualifier}{declarator}"), !mutable),
+        }
+    }
+}
+
+/// The C name for the Rust type or libc alias `name`.
+fn c_name(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "i8" => "int8_t",
+        "i16" => "int16_t",
+        "i32" => "int32_t",
+        "i64" => "int64_t",
+        "u8" => "uint8_t",
+        "u16" => "uint16_t",
+        "u32" => "uint32_t",
+        "u64" => "uint64_t",
+        "isize" => "intptr_t",
+        "usize" => "uintptr_t",
+        "f32" | "c_float" => "float",
+        "f64" | "c_double" => "double",
+        "bool" => "bool",
+        "c_char" => "char",
+        "c_schar" => "signed char",
+        "c_uchar" => "unsigned char",
+        "c_short" => "short",
+        "c_ushort" => "unsigned short",
+        "c_int" => "int",
+        "c_uint" => "unsigned int",
+        "c_long" => "long",
+        "c_ulong" => "unsigned long",
+        "c_longlong" => "long long",
+        "c_ulonglong" => "unsigned long long",
+        "c_void" => "void",
+        "size_t" => "size_t",
+        "ssize_t" => "ssize_t",
+        _ => return None,
+    })
+}
+
+fn c_type(ty: &syn::Type) -> Option<CType> {
+    match ty {
+        syn::Type::Path(path) if path.qself.is_none() => {
+            let segments = &path.path.segments;
+            let first = segments.first()?.ident.to_string();
+            // `libc::c_int`, `core::ffi::c_int` or just `c_int`, but not some other crate's.
+            if segments.len() > 1 && first != "libc" && first != "core" {
+                return None;
+            }
+            if segments.iter().any(|segment| !segment.arguments.is_none()) {
+                return None;
+            }
+            c_name(&segments.last()?.ident.to_string()).map(CType::Named)
+        }
+        syn::Type::Ptr(pointer) => Some(CType::Pointer {
+            pointee: Box::new(c_type(&pointer.elem)?),
+            mutable: pointer.mutability.is_some(),
+        }),
+        syn::Type::Paren(paren) => c_type(&paren.elem),
+        syn::Type::Group(group) => c_type(&group.elem),
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => Some(CType::Named("void")),
+        syn::Type::Never(_) => Some(CType::Named("void")),
+        _ => None,
+    }
+}
+
+fn is_no_mangle(attrs: &[syn::Attribute]) -> bool {
+    attrs.iter().any(|attr| attr.path().is_ident("no_mangle"))
+}
+
+/// The value of a constant, if it's something C can have as a macro.
+fn c_value(expr: &syn::Expr) -> Option<String> {
+    match expr {
+        syn::Expr::Lit(lit) => match &lit.lit {
+            syn::Lit::Int(int) => Some(int.base10_digits().to_string()),
+            syn::Lit::Float(float) => Some(float.base10_digits().to_string()),
+            syn::Lit::Bool(lit) => Some(lit.value.to_string()),
+            _ => None,
+        },
+        syn::Expr::Unary(syn::ExprUnary { op: syn::UnOp::Neg(_), expr, .. }) => {
+            c_value(expr).filter(|value| value.starts_with(|c: char| c.is_ascii_digit())).map(|value| format!("-{value}"))
+        }
+        syn::Expr::Paren(paren) => c_value(&paren.expr),
+        _ => None,
+    }
+}
+
+/// Writes out the declarations for `file`, reporting what C can't be told about.
+pub(crate) struct HeaderWriter<'a> {
+    pub(crate) spans: &'a SourceSpans,
+    pub(crate) diagnostics: &'a Diagnostics,
+}
+
+impl HeaderWriter<'_> {
+    /// The header for `file`, which was compiled from the source `name`.
+    pub(crate) fn write(&self, file: &syn::File, name: &str) -> String {
+        let mut declarations = vec![];
+        for item in &file.items {
+            match item {
+                syn::Item::Fn(f) if is_no_mangle(&f.attrs) && Self::is_c_abi(&f.sig.abi) => {
+                    declarations.extend(self.function(&f.sig));
+                }
+                syn::Item::Static(s) if is_no_mangle(&s.attrs) => {
+                    let Some(ty) = self.exported_type(&s.ty, &s.ident) else {
+                        continue;
+                    };
+                    let is_const = matches!(s.mutability, syn::StaticMutability::None);
+                    declarations.push(format!("extern {};", ty.declare(&s.ident.to_string(), is_const)));
+                }
+                syn::Item::Const(c) => declarations.extend(self.constant(c)),
+                _ => {}
+            }
+        }
+
+        let stem = Path::new(name).file_stem().unwrap_or_default().to_string_lossy();
+        let guard: String = stem
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        let guard = format!("{}_H", guard.trim_matches('_'));
+        let mut header = format!("/* Generated by crustc from {name}. */\n\n");
+        header.push_str(&format!("#ifndef {guard}\n#define {guard}\n\n"));
+        header.push_str("#include <stdbool.h>\n#include <stddef.h>\n#include <stdint.h>\n\n");
+        header.push_str("#ifdef __cplusplus\nextern \"C\" {\n#endif\n\n");
+        for declaration in &declarations {
+            header.push_str(declaration);
+            header.push('\n');
+        }
+        if !declarations.is_empty() {
+            header.push('\n');
+        }
+        header.push_str("#ifdef __cplusplus\n}\n#endif\n\n");
+        header.push_str(&format!("#endif /* {guard} */\n"));
+        header
+    }
+
+    /// `extern "C"`, which is also what a bare `extern` means.
+    fn is_c_abi(abi: &Option<syn::Abi>) -> bool {
+        abi.as_ref().is_some_and(|abi| abi.name.as_ref().is_none_or(|name| name.value() == "C"))
+    }
+
+    fn function(&self, sig: &syn::Signature) -> Option<String> {
+        let mut params = vec![];
+        let mut declarable = true;
+        for input in &sig.inputs {
+            let syn::FnArg::Typed(param) = input else {
+                continue;
+            };
+            let name = match &*param.pat {
+                syn::Pat::Ident(ident) => ident.ident.to_string(),
+                _ => String::new(),
+            };
+            match self.exported_type(&param.ty, &sig.ident) {
+                Some(ty) => params.push(ty.declare(&name, false)),
+                None => declarable = false,
+            }
+        }
+        let ret = match &sig.output {
+            syn::ReturnType::Default => Some(CType::Named("void")),
+            syn::ReturnType::Type(_, ty) => self.exported_type(ty, &sig.ident),
+        };
+        let ret = ret.filter(|_| declarable)?;
+
+        if sig.variadic.is_some() {
+            params.push("...".to_string());
+        }
+        let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+        Some(format!("{};", ret.declare(&format!("{}({params})", sig.ident), false)))
+    }
+
+    /// The C type of something `item` exports, or `None` once it's been reported.
+    fn exported_type(&self, ty: &syn::Type, item: &syn::Ident) -> Option<CType> {
+        let c_type = c_type(ty);
+        if c_type.is_none() {
+            let mut diagnostic = Diagnostic::error(format!("`{item}` can't be declared in C"))
+                .code(error_codes::C0008)
+                .help("use fixed-size integers, floats, `bool`, libc's C types, or pointers to them");
+            if let Some(span) = self.spans.source_range_of(ty.to_token_stream()) {
+                diagnostic = diagnostic.label(span, "C has no type like this");
+            }
+            self.diagnostics.emit(diagnostic);
+        }
+        c_type
+    }
+
+    fn constant(&self, c: &syn::ItemConst) -> Option<String> {
+        let declaration = match (c_type(&c.ty), c_value(&c.expr)) {
+            (Some(ty @ CType::Named(_)), Some(value)) => Some(format!("#define {} (({}){value})", c.ident, ty.declare("", false))),
+            _ => None,
+        };
+        if declaration.is_none() {
+            let mut diagnostic = Diagnostic::warning(format!("`{}` is left out of the C header", c.ident));
+            if let Some(span) = self.spans.source_range_of(c.to_token_stream()) {
+                diagnostic = diagnostic.label(span, "only constants of C number types with literal values are declared");
+            }
+            self.diagnostics.emit(diagnostic);
+        }
+        declaration
+    }
+}
@@ -0,0 +1,533 @@
+//! Command-line parsing for crustc.
+//!
+//! Flags follow rustc's spelling where there is an equivalent, so `-o`, `--emit`, `--target`,
+//! `-O`, `-C`, `-L` and `-l` mean what they mean there. Anything that only makes sense for
+//! Crust (`--panic-handler`, `--libc-path`, `--no-fmt`, `--rustfmt`, `--watch`) is long-form
+//! only.
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    diagnostics::{self, Diagnostic, ErrorFormat},
+    project, CrateType, PanicHandler,
+};
+
+pub const HELP: &str = "\
+Usage: crustc [OPTIONS] INPUT
+       crustc run [OPTIONS] INPUT [-- ARGS...]
+       crustc check [OPTIONS] INPUT
+       crustc build [OPTIONS] [MANIFEST]
+
+INPUT is a .crs file, or - to read the source from stdin. `run` builds the program into a
+temporary directory and runs it with ARGS, exiting with its status. `check` reports the
+errors and warnings a build would, without writing anything, so it needs a libc crate
+from an earlier build or --libc-path. With --watch, any of these
+goes again every time INPUT changes, until interrupted.
+
+`build` builds every crate in MANIFEST [default: crust.build] into `build/` next to it,
+libraries first, with OPTIONS applying to all of them.
+
+For library crate types, the `bin` output is the library: libNAME.rlib, .a or .so. The
+`c-header` output declares its `#[no_mangle] extern \"C\"` functions, statics and constants.
+
+Options:
+    -o PATH                 Write output to PATH
+        --emit KIND[,KIND]  Outputs to produce: rs, bin, obj, asm, llvm-ir, c-header
+                            [default: rs,bin, or just rs with --stdout]
+        --stdout            Print the generated Rust to stdout instead of a file
+        --no-fmt            Don't pretty-print the generated Rust
+        --rustfmt           Also run rustfmt over the generated Rust
+        --watch             Build again whenever the source changes
+        --target TRIPLE     Target triple to build for
+        --crate-type TYPE   What to build: bin, lib, staticlib or cdylib [default: bin]
+        --extern NAME=PATH  Let the source use the Crust library at PATH as NAME
+    -O                      Build with optimizations
+    -g                      Build with debug info that steps through the .crs source
+    -C OPT[=VALUE]          Pass a codegen option through to rustc
+    -L PATH                 Add a directory to the library search path
+    -l NAME                 Link the native library NAME
+        --edition 2021      Crust only supports edition 2021
+        --error-format FORMAT
+                            How to write errors: human or json [default: human]
+        --panic-handler KIND
+                            What panics do: abort, print or user [default: print]
+        --libc-path PATH    Use this libc rlib instead of the cached build
+    -h, --help              Print this message
+        --explain CODE      Explain an error code, like C0001 or E0308
+    -V, --version           Print version info
+";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Rs,
+    Bin,
+    Obj,
+    Asm,
+    LlvmIr,
+    CHeader,
+}
+
+impl Emit {
+    /// The name rustc gives this output in `--emit`, or `None` for the generated Rust and
+    /// the C header, which crustc writes itself.
+    pub fn rustc_name(self) -> Option<&'static str> {
+        match self {
+            Self::Rs => None,
+            Self::Bin => Some("link"),
+            Self::Obj => Some("obj"),
+            Self::Asm => Some("asm"),
+            Self::LlvmIr => Some("llvm-ir"),
+            Self::CHeader => None,
+        }
+    }
+
+    /// Where this output goes when `-o` doesn't say.
+    pub fn default_path(self, input: &Path, crate_type: CrateType) -> PathBuf {
+        match self {
+            Self::Rs => PathBuf::from(format!("{}.generated.rs", input.display())),
+            Self::Bin if crate_type != CrateType::Bin => input.with_file_name(crate_type.file_name(&crate_name_of(input))),
+            Self::Bin => input.with_extension(""),
+            Self::Obj => input.with_extension("o"),
+            Self::Asm => input.with_extension("s"),
+            Self::LlvmIr => input.with_extension("ll"),
+            Self::CHeader => input.with_extension("h"),
+        }
+    }
+}
+
+impl std::str::FromStr for Emit {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rs" => Ok(Self::Rs),
+            "bin" => Ok(Self::Bin),
+            "obj" => Ok(Self::Obj),
+            "asm" => Ok(Self::Asm),
+            "llvm-ir" => Ok(Self::LlvmIr),
+            "c-header" => Ok(Self::CHeader),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The crate name rustc would give a crate built from `path`.
+fn crate_name_of(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().replace(['-', '.'], "_"))
+        .unwrap_or_else(|| "main".to_string())
+}
+
+pub struct Options {
+    /// The source file, or `-` for stdin.
+    pub input: String,
+    /// Index of `input` in the arguments, for pointing at it in errors.
+    pub input_index: usize,
+    pub output: Option<PathBuf>,
+    pub emit: Vec<Emit>,
+    pub stdout: bool,
+    pub fmt: bool,
+    pub rustfmt: bool,
+    pub watch: bool,
+    pub target: Option<String>,
+    pub optimize: bool,
+    pub debuginfo: bool,
+    pub codegen: Vec<String>,
+    pub link_paths: Vec<String>,
+    pub link_libs: Vec<String>,
+    pub panic_handler: PanicHandler,
+    pub libc_path: Option<PathBuf>,
+    pub error_format: ErrorFormat,
+    pub crate_type: CrateType,
+    /// The crate name, if it isn't the input's, which only `crustc build` sets.
+    pub crate_name: Option<String>,
+    /// Crates built from other Crust sources that this one uses, by name and rlib.
+    pub externs: Vec<(String, PathBuf)>,
+}
+
+impl Options {
+    pub fn emits(&self, kind: Emit) -> bool {
+        self.emit.contains(&kind)
+    }
+
+    pub fn reads_stdin(&self) -> bool {
+        self.input == "-"
+    }
+
+    /// What diagnostics call the source.
+    pub fn source_name(&self) -> &str {
+        if self.reads_stdin() { "<stdin>" } else { &self.input }
+    }
+
+    /// The path outputs are named after: the input, or `rust_out` for stdin, as in rustc.
+    pub fn output_stem(&self) -> &Path {
+        Path::new(if self.reads_stdin() { "rust_out" } else { &self.input })
+    }
+
+    /// The name of the crate being built.
+    pub fn crate_name(&self) -> String {
+        self.crate_name.clone().unwrap_or_else(|| crate_name_of(self.output_stem()))
+    }
+
+    /// Where the `kind` output is written. `-o` names the single output when there is only
+    /// one, and otherwise the compiled one, leaving the files crustc writes itself at their
+    /// usual paths.
+    pub fn output_path(&self, kind: Emit) -> PathBuf {
+        let input = self.output_stem();
+        let Some(output) = &self.output else {
+            return kind.default_path(input, self.crate_type);
+        };
+
+        let compiled: Vec<Emit> = self.emit.iter().copied().filter(|emit| emit.rustc_name().is_some()).collect();
+        match (kind.rustc_name(), compiled.as_slice()) {
+            (None, _) if self.emit.len() == 1 => output.clone(),
+            (None, _) => kind.default_path(input, self.crate_type),
+            (Some(_), [_]) => output.clone(),
+            _ => kind.default_path(output, self.crate_type),
+        }
+    }
+}
+
+pub enum Command {
+    Compile(Box<Options>),
+    /// `crustc run`, with the arguments for the program.
+    Run(Box<Options>, Vec<String>),
+    /// `crustc check`.
+    Check(Box<Options>),
+    /// `crustc build`, with the manifest as the input.
+    Build(Box<Options>),
+    Help,
+    Version,
+    /// `--explain CODE`, for a Crust or rustc error code.
+    Explain(String),
+}
+
+pub struct ArgError {
+    /// The offending argument, or `args.len()` if one is missing at the end.
+    pub index: usize,
+    pub kind: ArgErrorKind,
+}
+
+pub enum ArgErrorKind {
+    NoInput,
+    UnknownFlag,
+    MissingValue(&'static str),
+    BadEmit,
+    BadEdition,
+    BadPanicHandler,
+    BadErrorFormat,
+    BadCrateType,
+    /// An `--extern` that isn't `NAME=PATH`.
+    BadExtern,
+    ExtraInput,
+    /// An output flag given to a subcommand, which decides its own outputs.
+    NotForSubcommand(&'static str),
+    /// `--watch` with the source on stdin, which can't change.
+    WatchStdin,
+    /// `--watch` given to `crustc build`, which has more than one source to watch.
+    WatchBuild,
+}
+
+pub fn parse(args: &[String]) -> Result<Command, ArgError> {
+    let mut input = None;
+    let mut output = None;
+    let mut emit = None;
+    let mut stdout = false;
+    let mut fmt = true;
+    let mut rustfmt = false;
+    let mut watch = None;
+    let mut target = None;
+    let mut optimize = false;
+    let mut debuginfo = false;
+    let mut codegen = vec![];
+    let mut link_paths = vec![];
+    let mut link_libs = vec![];
+    let mut panic_handler = PanicHandler::Print;
+    let mut libc_path = None;
+    let mut error_format = ErrorFormat::Human;
+    let mut crate_type = CrateType::Bin;
+    let mut externs = vec![];
+
+    let subcommand = match args.get(1).map(String::as_str) {
+        Some("run") => Some("run"),
+        Some("check") => Some("check"),
+        Some("build") => Some("build"),
+        _ => None,
+    };
+    let run = subcommand == Some("run");
+    let mut program_args = vec![];
+    let mut output_flag = None;
+
+    let mut i = if subcommand.is_some() { 2 } else { 1 };
+    while i < args.len() {
+        let arg = args[i].as_str();
+        let error = |index, kind| Err(ArgError { index, kind });
+        if run && arg == "--" {
+            program_args = args[i + 1..].to_vec();
+            break;
+        }
+        if ["-o", "--emit", "--stdout"].iter().any(|flag| arg.starts_with(flag)) {
+            output_flag.get_or_insert(i);
+        }
+        // `check` is the one subcommand where the kind of crate still matters.
+        if arg.starts_with("--crate-type") && subcommand != Some("check") {
+            output_flag.get_or_insert(i);
+        }
+
+        // Flags that take a value accept it joined (`--emit=rs`, `-Copt-level=3`) or as the
+        // next argument.
+        let mut value = |name: &str, expected: &'static str| -> Result<Option<(usize, String)>, ArgError> {
+            let joined = if name.starts_with("--") {
+                arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('='))
+            } else {
+                arg.strip_prefix(name).filter(|rest| !rest.is_empty())
+            };
+            if let Some(joined) = joined {
+                return Ok(Some((i, joined.to_string())));
+            }
+            if arg != name {
+                return Ok(None);
+            }
+            i += 1;
+            match args.get(i) {
+                Some(next) => Ok(Some((i, next.clone()))),
+                None => Err(ArgError { index: i, kind: ArgErrorKind::MissingValue(expected) }),
+            }
+        };
+
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        } else if arg == "-V" || arg == "--version" {
+            return Ok(Command::Version);
+        } else if let Some((_, code)) = value("--explain", "an error code")? {
+            return Ok(Command::Explain(code));
+        } else if arg == "--stdout" {
+            stdout = true;
+        } else if arg == "--no-fmt" {
+            fmt = false;
+        } else if arg == "--rustfmt" {
+            rustfmt = true;
+        } else if arg == "--watch" {
+            watch = Some(i);
+        } else if arg == "-O" {
+            optimize = true;
+        } else if arg == "-g" {
+            debuginfo = true;
+        } else if let Some((_, path)) = value("-o", "an output path")? {
+            output = Some(PathBuf::from(path));
+        } else if let Some((index, kinds)) = value("--emit", "an output kind")? {
+            emit = match kinds.split(',').map(str::parse).collect::<Result<Vec<_>, _>>() {
+                Ok(kinds) if !kinds.is_empty() => Some(kinds),
+                _ => return error(index, ArgErrorKind::BadEmit),
+            };
+        } else if let Some((_, triple)) = value("--target", "a target triple")? {
+            target = Some(triple);
+        } else if let Some((_, opt)) = value("-C", "a codegen option")? {
+            codegen.push(opt);
+        } else if let Some((_, path)) = value("-L", "a library search path")? {
+            link_paths.push(path);
+        } else if let Some((_, lib)) = value("-l", "a library name")? {
+            link_libs.push(lib);
+        } else if let Some((index, edition)) = value("--edition", "an edition")? {
+            if edition != "2021" {
+                return error(index, ArgErrorKind::BadEdition);
+            }
+        } else if let Some((index, handler)) = value("--panic-handler", "a panic handler")? {
+            let Ok(handler) = handler.parse() else {
+                return error(index, ArgErrorKind::BadPanicHandler);
+            };
+            panic_handler = handler;
+        } else if let Some((index, kind)) = value("--crate-type", "a crate type")? {
+            let Ok(kind) = kind.parse() else {
+                return error(index, ArgErrorKind::BadCrateType);
+            };
+            crate_type = kind;
+        } else if let Some((index, spec)) = value("--extern", "a crate name and path")? {
+            let Some((name, path)) = spec.split_once('=').filter(|(name, path)| !name.is_empty() && !path.is_empty()) else {
+                return error(index, ArgErrorKind::BadExtern);
+            };
+            externs.push((name.to_string(), PathBuf::from(path)));
+        } else if let Some((_, path)) = value("--libc-path", "a path to a libc rlib")? {
+            libc_path = Some(PathBuf::from(path));
+        } else if let Some((index, format)) = value("--error-format", "an error format")? {
+            let Ok(format) = format.parse() else {
+                return error(index, ArgErrorKind::BadErrorFormat);
+            };
+            error_format = format;
+        } else if arg.starts_with('-') && arg != "-" {
+            return error(i, ArgErrorKind::UnknownFlag);
+        } else if input.is_some() {
+            return error(i, ArgErrorKind::ExtraInput);
+        } else {
+            input = Some((i, arg.to_string()));
+        }
+
+        i += 1;
+    }
+
+    let input = match input {
+        Some(input) => input,
+        None if subcommand == Some("build") => (args.len(), project::DEFAULT_MANIFEST.to_string()),
+        None => return Err(ArgError { index: args.len(), kind: ArgErrorKind::NoInput }),
+    };
+    let (input_index, input) = input;
+    if let (Some(index), Some(subcommand)) = (output_flag, subcommand) {
+        return Err(ArgError { index, kind: ArgErrorKind::NotForSubcommand(subcommand) });
+    }
+    if let Some(index) = watch.filter(|_| subcommand == Some("build")) {
+        return Err(ArgError { index, kind: ArgErrorKind::WatchBuild });
+    }
+    if watch.is_some() && input == "-" {
+        return Err(ArgError { index: input_index, kind: ArgErrorKind::WatchStdin });
+    }
+    // Printing the Rust is usually all that's wanted from a pipeline.
+    let emit = emit.unwrap_or_else(|| if stdout { vec![Emit::Rs] } else { vec![Emit::Rs, Emit::Bin] });
+
+    let options = Box::new(Options {
+        input,
+        input_index,
+        output,
+        emit,
+        stdout,
+        fmt,
+        rustfmt,
+        watch: watch.is_some(),
+        target,
+        optimize,
+        debuginfo,
+        codegen,
+        link_paths,
+        link_libs,
+        panic_handler,
+        libc_path,
+        error_format,
+        crate_type,
+        crate_name: None,
+        externs,
+    });
+    Ok(match subcommand {
+        Some("run") => Command::Run(options, program_args),
+        Some("check") => Command::Check(options),
+        Some(_) => Command::Build(options),
+        None => Command::Compile(options),
+    })
+}
+
+/// Joins `args` into one line, returning it with the byte range of `args[index]`, or an
+/// empty range at the end if `index` is past the last argument.
+fn command_line(args: &[impl AsRef<str>], index: usize) -> (String, std::ops::Range<usize>) {
+    let mut line = String::new();
+    let mut span = None;
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            line.push(' ');
+        }
+        if i == index {
+            span = Some(line.len()..line.len() + arg.as_ref().len());
+        }
+        line.push_str(arg.as_ref());
+    }
+    let span = span.unwrap_or(line.len()..line.len());
+    (line, span)
+}
+
+/// The `--error-format` asked for, looked up separately so that errors in the rest of the
+/// arguments are reported in the right format too.
+pub fn error_format(args: &[impl AsRef<str>]) -> ErrorFormat {
+    let mut format = ErrorFormat::Human;
+    for (i, arg) in args.iter().enumerate() {
+        let value = match arg.as_ref().strip_prefix("--error-format") {
+            Some("") => args.get(i + 1).map(AsRef::as_ref),
+            Some(rest) => rest.strip_prefix('='),
+            None => None,
+        };
+        if let Some(value) = value.and_then(|value| value.parse().ok()) {
+            format = value;
+        }
+    }
+    format
+}
+
+fn report_arg_error(args: &[impl AsRef<str>], index: usize, title: &str, label: &str, help: Option<&str>) {
+    let (line, span) = command_line(args, index);
+
+    let mut diagnostic = Diagnostic::error(title).label(span, label);
+    if let Some(help) = help {
+        diagnostic = diagnostic.help(help);
+    }
+    diagnostics::report(error_format(args), &diagnostic, &line, None);
+}
+
+pub fn report_error(args: &[impl AsRef<str>], err: &ArgError) {
+    match &err.kind {
+        ArgErrorKind::NoInput => report_error_not_enough_args(args),
+        ArgErrorKind::UnknownFlag => {
+            report_arg_error(args, err.index, "unknown flag", "crustc doesn't know this flag", Some("run `crustc --help` for the flags it does know"));
+        }
+        ArgErrorKind::MissingValue(expected) => {
+            let title = format!("`{}` needs a value", args[err.index - 1].as_ref());
+            let label = format!("expected {expected} here");
+            report_arg_error(args, err.index, &title, &label, None);
+        }
+        ArgErrorKind::BadEmit => {
+            report_arg_error(args, err.index, "invalid output kind", "expected a comma-separated list of `rs`, `bin`, `obj`, `asm`, `llvm-ir` or `c-header`", None);
+        }
+        ArgErrorKind::BadEdition => {
+            report_arg_error(args, err.index, "unsupported edition", "Crust is only defined for edition 2021", None);
+        }
+        ArgErrorKind::BadPanicHandler => report_error_bad_panic_handler(args, err.index),
+        ArgErrorKind::BadErrorFormat => {
+            report_arg_error(args, err.index, "invalid error format", "expected `human` or `json`", None);
+        }
+        ArgErrorKind::BadCrateType => {
+            report_arg_error(args, err.index, "invalid crate type", "expected `bin`, `lib`, `staticlib` or `cdylib`", None);
+        }
+        ArgErrorKind::BadExtern => {
+            let help = "name the crate the way the source uses it, like `--extern mathx=libmathx.rlib`";
+            report_arg_error(args, err.index, "invalid `--extern`", "expected `NAME=PATH`", Some(help));
+        }
+        ArgErrorKind::ExtraInput => {
+            report_arg_error(args, err.index, "more than one source file", "crustc compiles one file at a time", None);
+        }
+        ArgErrorKind::NotForSubcommand("run") => {
+            let help = "arguments for the program go after `--`";
+            report_arg_error(args, err.index, "output flag given to `crustc run`", "`run` builds into a temporary directory", Some(help));
+        }
+        ArgErrorKind::NotForSubcommand("build") => {
+            let label = "`build` writes into `build/` next to the manifest";
+            report_arg_error(args, err.index, "output flag given to `crustc build`", label, None);
+        }
+        ArgErrorKind::NotForSubcommand(subcommand) => {
+            let title = format!("output flag given to `crustc {subcommand}`");
+            let label = format!("`{subcommand}` doesn't write anything");
+            report_arg_error(args, err.index, &title, &label, None);
+        }
+        ArgErrorKind::WatchStdin => {
+            report_arg_error(args, err.index, "can't watch stdin", "`--watch` needs a source file to watch", None);
+        }
+        ArgErrorKind::WatchBuild => {
+            report_arg_error(args, err.index, "can't watch a project", "`--watch` only watches a single source file", None);
+        }
+    }
+}
+
+pub fn report_error_not_enough_args(args: &[impl AsRef<str>]) {
+    report_arg_error(args, args.len(), "not enough arguments", "expected path to source file here", None);
+}
+
+pub fn report_error_failed_to_open_source_file(args: &[impl AsRef<str>], index: usize) {
+    report_arg_error(args, index, "failed to open source file", "couldn't open this file", None);
+}
+
+pub fn report_error_failed_to_open_manifest(args: &[impl AsRef<str>], index: usize, manifest: &str) {
+    let label = format!("couldn't open `{manifest}`");
+    report_arg_error(args, index, "failed to open build manifest", &label, None);
+}
+
+pub fn report_error_failed_to_read_stdin(args: &[impl AsRef<str>], index: usize) {
+    report_arg_error(args, index, "failed to read source from stdin", "couldn't read stdin as UTF-8 text", None);
+}
+
+pub fn report_error_bad_panic_handler(args: &[impl AsRef<str>], index: usize) {
+    report_arg_error(args, index, "invalid panic handler", "expected one of `abort`, `print` or `user`", None);
+}
@@ -0,0 +1,79 @@
+//! Carrying comments from the `.crs` over to the generated Rust.
+//!
+//! The generated file is printed from `syn`, which has nowhere to keep comments, so they're
+//! put back into the printed text afterwards. Each one goes just before whatever was compiled
+//! from the first source token after it, which is the item or statement it was written above.
+//! Doc comments are dropped from the generated items, so they come back the same way.
+
+use std::ops::Range;
+
+use rustc_lexer::TokenKind;
+
+use crate::span_map::{self, SpanMap};
+
+/// The comments in `source`, in order.
+pub fn collect(source: &str) -> Vec<Range<usize>> {
+    let mut comments = vec![];
+    let mut pos = 0;
+    for token in rustc_lexer::tokenize(source) {
+        let range = pos..pos + token.len as usize;
+        pos = range.end;
+        match token.kind {
+            TokenKind::LineComment { .. } | TokenKind::BlockComment { .. } => comments.push(range),
+            _ => {}
+        }
+    }
+    comments
+}
+
+/// `text` with its comments overwritten by spaces, leaving every other byte where it was.
+pub fn blank(text: &str) -> String {
+    let mut bytes = text.as_bytes().to_vec();
+    for comment in collect(text) {
+        for byte in bytes[comment].iter_mut().filter(|byte| **byte != b'\n') {
+            *byte = b' ';
+        }
+    }
+    String::from_utf8(bytes).expect("only whole comments were overwritten")
+}
+
+/// Puts the comments of `source` back into `generated`, which `map` describes.
+pub fn insert(map: &SpanMap, generated: &str, source: &str) -> String {
+    let tokens = span_map::token_ranges(generated);
+    let inside_token = |pos: usize| tokens.iter().any(|token| token.start < pos && pos < token.end);
+
+    // Where each comment goes, and what goes there. Comments with nothing after them end the file.
+    let mut insertions = vec![];
+    for comment in collect(source) {
+        let text = source[comment.clone()].trim_end();
+        let Some(target) = map.generated_after(comment.end) else {
+            insertions.push((generated.len(), format!("\n{text}\n")));
+            continue;
+        };
+
+        // Above the line the target starts, if that's where its item or statement starts,
+        // and otherwise right before it.
+        let line_start = generated[..target].rfind('\n').map_or(0, |newline| newline + 1);
+        let indent = &generated[line_start..target];
+        let indent = &indent[..indent.len() - indent.trim_start().len()];
+        if !map.maps_any(line_start..target) && !inside_token(line_start) {
+            insertions.push((line_start, format!("{indent}{text}\n")));
+        } else {
+            insertions.push((target, format!("\n{text}\n")));
+        }
+    }
+
+    // Comments headed for the same spot stay in source order.
+    insertions.sort_by_key(|(pos, _)| *pos);
+    let mut out = String::with_capacity(generated.len());
+    let mut copied = 0;
+    for (i, (pos, text)) in insertions.iter().enumerate() {
+        out.push_str(&generated[copied..*pos]);
+        // Comments put right before a token already end with a newline for the next one.
+        let follows_comment = i > 0 && insertions[i - 1].0 == *pos;
+        out.push_str(if follows_comment { text.trim_start_matches('\n') } else { text });
+        copied = *pos;
+    }
+    out.push_str(&generated[copied..]);
+    out
+}
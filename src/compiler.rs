@@ -0,0 +1,1635 @@
+//! Compiling a Crust crate to Rust.
+//!
+//! [`CrustCompiler`] runs as rustc's callbacks: it checks the parsed crate for what Crust
+//! doesn't allow, then walks the HIR once macros are expanded and names resolved, building
+//! up the generated file as a `syn` tree.
+
+use quote::{ToTokens, quote};
+use rustc_driver::Callbacks;
+use rustc_interface::interface;
+use rustc_hir::intravisit::{self, Visitor};
+use rustc_middle::ty::{TyCtxt, TypeckResults};
+use rustc_session::config::Input;
+use rustc_span::{source_map::SourceMap, FileName, RealFileName};
+use std::{
+    collections::{HashMap, HashSet}, ffi::CString, ops::Range, path::PathBuf, str::FromStr, sync::Arc,
+};
+
+use syn::{self, Token};
+
+use crate::{
+    c_header::HeaderWriter,
+    diagnostics::{self, Diagnostic, Diagnostics},
+    error_codes, printf,
+    span_map::{EmittedToken, SourceSpans},
+    CrateType, PanicHandler,
+};
+
+
+/// Reports `$what` at `$span` as unsupported by crustc, evaluating to `$default` so the
+/// rest of the file still gets checked. Nothing is written once an error is reported, so
+/// the placeholder never ends up in a generated file.
+macro_rules! unsupported {
+    ($self:expr, $span:expr, $what:expr) => {{
+        $self.report_unsupported($span, $what);
+    }};
+    ($self:expr, $span:expr, $default:expr, $what:expr) => {{
+        $self.report_unsupported($span, $what);
+        $default
+    }};
+}
+
+pub(crate) struct CrustCompiler {
+    pub(crate) source: String,
+    source_filename: String,
+    pub(crate) outfile: syn::File,
+    parsed_infos: HashMap<rustc_span::Span, rustc_ast::Item>,
+    /// Every macro call in the source, keyed by where its path starts. Macros are expanded
+    /// before we see the HIR, so this is how we get back to what the user actually wrote.
+    macro_calls: HashMap<rustc_span::BytePos, rustc_ast::MacCall>,
+    compiled_uses: HashSet<rustc_span::Span>,
+    panic_handler: PanicHandler,
+    crate_type: CrateType,
+    user_panic_handler: Option<rustc_span::Ident>,
+    pub(crate) diagnostics: Diagnostics,
+    /// rustc's, once it has loaded the source, to find the source's spans in.
+    source_map: Option<Arc<SourceMap>>,
+    source_spans: SourceSpans,
+    /// `outfile` flattened once it's done, with where each token came from. The spans in
+    /// `outfile` itself can't be looked up outside rustc's thread.
+    pub(crate) emitted_tokens: Vec<EmittedToken>,
+    /// Whether to declare what the crate exports for C, in `c_header`.
+    pub(crate) wants_c_header: bool,
+    pub(crate) c_header: Option<String>,
+}
+
+unsafe impl Send for CrustCompiler {}
+unsafe impl Sync for CrustCompiler {}
+
+impl CrustCompiler {
+    /// A compiler for `source`, which diagnostics call `filename`.
+    pub(crate) fn new(
+        source: String,
+        filename: String,
+        panic_handler: PanicHandler,
+        crate_type: CrateType,
+        diagnostics: Diagnostics,
+    ) -> Self {
+        Self {
+            source,
+            source_filename: filename,
+            outfile: syn::File {
+                shebang: None,
+                items: vec![],
+                attrs: vec![
+                    syn::parse_quote! { #![no_std] },
+                ],
+            },
+            parsed_infos: HashMap::new(),
+            macro_calls: HashMap::new(),
+            compiled_uses: HashSet::new(),
+            panic_handler,
+            crate_type,
+            user_panic_handler: None,
+            diagnostics,
+            source_map: None,
+            source_spans: SourceSpans::default(),
+            emitted_tokens: vec![],
+            wants_c_header: false,
+            c_header: None,
+        }
+    }
+}
+
+impl Callbacks for CrustCompiler {
+    fn config(&mut self, config: &mut interface::Config) {
+        // rustc is given the file name to report errors against, but compiles our copy.
+        let filename = self.rustc_filename();
+        config.input = Input::Str { name: filename.clone(), input: self.source.clone() };
+        let for_rustc = self.diagnostics.for_rustc(filename);
+        config.psess_created = Some(Box::new(move |psess| for_rustc.install(psess)));
+    }
+
+    fn after_crate_root_parsing(
+        &mut self,
+        compiler: &interface::Compiler,
+        krate: &mut rustc_ast::Crate
+    ) -> rustc_driver::Compilation {
+        use rustc_ast::ItemKind;
+        self.source_map = Some(compiler.sess.psess.clone_source_map());
+        self.source_spans = SourceSpans::new(&self.source);
+        for item in &mut krate.items {
+            if let ItemKind::Fn(f) = &item.kind {
+                let ident = f.ident;
+                if Self::take_crust_attr(&mut item.attrs, "panic_handler") {
+                    if self.user_panic_handler.is_some() {
+                        self.report_duplicate_panic_handler(item.span);
+                        continue;
+                    }
+                    self.user_panic_handler = Some(ident);
+                    self.panic_handler = PanicHandler::User;
+                }
+            }
+            self.parsed_infos.insert(item.span, (**item).clone());
+        }
+
+        let mut collector = MacroCallCollector::default();
+        rustc_ast::visit::walk_crate(&mut collector, krate);
+        self.macro_calls = collector.calls;
+
+        let mut finder = StdUsageFinder::default();
+        rustc_ast::visit::walk_crate(&mut finder, krate);
+        for usage in &finder.usages {
+            self.report_std_usage(usage);
+        }
+
+        if self.panic_handler == PanicHandler::User && self.user_panic_handler.is_none() {
+            self.report_missing_panic_handler();
+        }
+
+        self.flush_diagnostics();
+        if self.diagnostics.has_errors() {
+            return rustc_driver::Compilation::Stop;
+        }
+        rustc_driver::Compilation::Continue
+    }
+
+    fn after_expansion<'tcx>(
+        &mut self,
+        _compiler: &interface::Compiler,
+        tcx: TyCtxt<'tcx>,
+    ) -> rustc_driver::Compilation {
+        for item_id in tcx.hir_free_items() {
+            let item = &tcx.hir_item(item_id);
+            self.compile_item(tcx, item.span, &item.kind);
+        }
+        if self.wants_c_header {
+            let writer = HeaderWriter { spans: &self.source_spans, diagnostics: &self.diagnostics };
+            self.c_header = Some(writer.write(&self.outfile, &self.source_filename));
+        }
+
+        self.flush_diagnostics();
+        if self.diagnostics.has_errors() {
+            return rustc_driver::Compilation::Stop;
+        }
+
+        if self.crate_type == CrateType::Bin {
+            if let Some(entry_point) = self.compile_entry_point() {
+                self.outfile.attrs.push(syn::parse_quote! { #![no_main] });
+                self.outfile.items.push(entry_point);
+            }
+        }
+        // A Rust library's panics are handled by whichever program it ends up in.
+        if self.crate_type.is_final() {
+            let panic_handler = self.compile_panic_handler();
+            self.outfile.items.push(panic_handler);
+            self.outfile.items.push(Self::compile_eh_personality());
+        }
+        self.emitted_tokens = self.source_spans.emitted_tokens(self.outfile.to_token_stream());
+
+        rustc_driver::Compilation::Stop
+    }
+}
+
+/// A use of `std` (or `alloc`) found in the source. Crust only allows `core` and libc.
+struct StdUsage {
+    span: rustc_span::Span,
+    label: String,
+    help: &'static str,
+}
+
+#[derive(Default)]
+struct StdUsageFinder {
+    usages: Vec<StdUsage>,
+}
+
+impl StdUsageFinder {
+    const BANNED_CRATES: &[&str] = &["std", "alloc"];
+
+    /// Std-only macros that are in scope without a path, paired with what to use instead.
+    /// `print!` and friends aren't here since crustc lowers them to printf.
+    const BANNED_MACROS: &[(&str, &str)] = &[
+        ("format", "format into a buffer with libc's `snprintf` instead"),
+        ("vec", "allocate with `libc::malloc` or `libc::calloc` instead"),
+    ];
+
+    fn check_path(&mut self, path: &rustc_ast::Path) {
+        let Some(first) = path.segments.iter().find(|seg| seg.ident.name != rustc_span::symbol::kw::PathRoot) else {
+            return;
+        };
+
+        let name = first.ident.name.as_str();
+        if Self::BANNED_CRATES.contains(&name) {
+            self.usages.push(StdUsage {
+                span: first.ident.span,
+                label: format!("`{name}` is not available in crust"),
+                help: if name == "alloc" {
+                    "allocate with `libc::malloc` or `libc::calloc` instead"
+                } else {
+                    "use `core` or `libc` instead"
+                },
+            });
+        }
+    }
+
+    fn is_empty_prefix(path: &rustc_ast::Path) -> bool {
+        path.segments.iter().all(|seg| seg.ident.name == rustc_span::symbol::kw::PathRoot)
+    }
+}
+
+impl<'ast> rustc_ast::visit::Visitor<'ast> for StdUsageFinder {
+    fn visit_item(&mut self, item: &'ast rustc_ast::Item) {
+        if let rustc_ast::ItemKind::ExternCrate(orig_name, ident) = &item.kind {
+            let name = orig_name.unwrap_or(ident.name);
+            if Self::BANNED_CRATES.contains(&name.as_str()) {
+                self.usages.push(StdUsage {
+                    span: item.span,
+                    label: format!("`extern crate {name}` is not allowed in crust"),
+                    help: "only `core` and `libc` may be linked",
+                });
+            }
+        }
+        rustc_ast::visit::walk_item(self, item);
+    }
+
+    fn visit_path(&mut self, path: &'ast rustc_ast::Path, _id: rustc_ast::NodeId) {
+        self.check_path(path);
+        rustc_ast::visit::walk_path(self, path);
+    }
+
+    fn visit_use_tree(&mut self, use_tree: &'ast rustc_ast::UseTree, _id: rustc_ast::NodeId, nested: bool) {
+        // Nested trees are relative to their parent, so only the outermost prefix names a crate
+        // (unless the parent is just `{...}` or `::{...}`).
+        if !nested {
+            self.check_path(&use_tree.prefix);
+        }
+
+        if let rustc_ast::UseTreeKind::Nested { items, .. } = &use_tree.kind {
+            let relative = !Self::is_empty_prefix(&use_tree.prefix);
+            for (tree, id) in items {
+                self.visit_use_tree(tree, *id, relative);
+            }
+        }
+    }
+
+    fn visit_mac_call(&mut self, mac: &'ast rustc_ast::MacCall) {
+        if let [seg] = mac.path.segments.as_slice() {
+            let name = seg.ident.name.as_str();
+            if let Some((_, help)) = Self::BANNED_MACROS.iter().find(|(banned, _)| *banned == name) {
+                self.usages.push(StdUsage {
+                    span: mac.span(),
+                    label: format!("`{name}!` is part of std"),
+                    help,
+                });
+            }
+        }
+        rustc_ast::visit::walk_mac(self, mac);
+    }
+}
+
+#[derive(Default)]
+struct MacroCallCollector {
+    calls: HashMap<rustc_span::BytePos, rustc_ast::MacCall>,
+}
+
+impl<'ast> rustc_ast::visit::Visitor<'ast> for MacroCallCollector {
+    fn visit_mac_call(&mut self, mac: &'ast rustc_ast::MacCall) {
+        self.calls.insert(mac.path.span.lo(), mac.clone());
+        rustc_ast::visit::walk_mac(self, mac);
+    }
+}
+
+/// Finds the expressions the user passed to a macro inside its expansion. These are the
+/// outermost expressions that didn't come from the expansion itself.
+struct MacroArgFinder<'hir> {
+    call_site: rustc_span::Span,
+    args: Vec<&'hir rustc_hir::Expr<'hir>>,
+}
+
+impl<'hir> Visitor<'hir> for MacroArgFinder<'hir> {
+    fn visit_expr(&mut self, expr: &'hir rustc_hir::Expr<'hir>) {
+        if !expr.span.from_expansion() && self.call_site.contains(expr.span) {
+            self.args.push(expr);
+            return;
+        }
+        intravisit::walk_expr(self, expr);
+    }
+}
+
+impl CrustCompiler {
+    fn compile_item<'tcx, 'hir>(&mut self, tcx: TyCtxt<'tcx>, span: rustc_span::Span, item: &rustc_hir::ItemKind<'hir>) {
+        let Some(parsed_info) = self.parsed_infos.get(&span) else {
+            return;
+        };
+
+        use rustc_hir::ItemKind as IK;
+        match item {
+            IK::ExternCrate(_sym, _id) => {
+                let rustc_ast::ItemKind::ExternCrate(orig_name, ident) = &parsed_info.kind else {
+                    self.report_mismatched_item(span, "an `extern crate`");
+                    return;
+                };
+
+                // `extern crate orig as ident;` names the crate first and what it's called here second.
+                let ident = self.compile_ident(ident);
+                let (crate_ident, rename) = match orig_name {
+                    Some(orig) => {
+                        let orig = syn::Ident::new(&orig.to_ident_string(), ident.span());
+                        (orig, Some((<syn::Token![as]>::default(), ident)))
+                    }
+                    None => (ident, None),
+                };
+                self.outfile.items.push(syn::Item::ExternCrate(syn::ItemExternCrate {
+                    attrs: self.compile_attrs(&parsed_info.attrs),
+                    vis: self.compile_vis(&parsed_info.vis),
+                    extern_token: <syn::Token![extern]>::default(),
+                    crate_token: <syn::Token![crate]>::default(),
+                    ident: crate_ident,
+                    rename,
+                    semi_token: <syn::Token![;]>::default(),
+                }));
+            }
+            IK::Use(_path, _kind) => {
+                // rustc splits `use a::{b, c}` into an item per name, but we want it back in one piece.
+                if !self.compiled_uses.insert(span) {
+                    return;
+                }
+                let rustc_ast::ItemKind::Use(tree) = &parsed_info.kind else {
+                    self.report_mismatched_item(span, "a `use`");
+                    return;
+                };
+
+                let attrs = self.compile_attrs(&parsed_info.attrs);
+                let vis = self.compile_vis(&parsed_info.vis);
+                let leading_colon = tree.prefix.segments.first()
+                    .filter(|seg| seg.ident.name == rustc_span::symbol::kw::PathRoot)
+                    .map(|_| <syn::Token![::]>::default());
+                let tree = self.compile_use_tree(tree);
+
+                self.outfile.items.push(syn::Item::Use(syn::ItemUse {
+                    attrs,
+                    vis,
+                    use_token: <syn::Token![use]>::default(),
+                    leading_colon,
+                    tree,
+                    semi_token: <syn::Token![;]>::default(),
+                }));
+            }
+            IK::Static(id, _ty, _mut, body_id) => {
+                let rustc_ast::ItemKind::Static(static_info) = &parsed_info.kind else {
+                    self.report_mismatched_item(span, "a static");
+                    return;
+                };
+
+                let attrs = self.compile_attrs(&parsed_info.attrs);
+                let vis = self.compile_vis(&parsed_info.vis);
+                let mutability = self.compile_mutability(*_mut);
+                let ident = self.compile_ident(id);
+                let ty = self.compile_type(&static_info.ty);
+
+                let tcr = tcx.typeck_body(*body_id);
+                let body = tcx.hir_body(*body_id);
+                assert!(body.params.is_empty());
+                let expr = self.compile_expr_hir(tcx, tcr, body.value);
+
+                self.outfile.items.push(syn::Item::Static(syn::ItemStatic {
+                    attrs,
+                    vis,
+                    static_token: <syn::Token![static]>::default(),
+                    mutability,
+                    ident,
+                    colon_token: <syn::Token![:]>::default(),
+                    ty: Box::new(ty),
+                    eq_token: <syn::Token![=]>::default(),
+                    expr: Box::new(expr),
+                    semi_token: <syn::Token![;]>::default(),
+                }));
+            }
+            IK::Const(id, _ty, generics, body_id) => {
+                let rustc_ast::ItemKind::Const(const_info) = &parsed_info.kind else {
+                    self.report_mismatched_item(span, "a constant");
+                    return;
+                };
+
+                let attrs = self.compile_attrs(&parsed_info.attrs);
+                let vis = self.compile_vis(&parsed_info.vis);
+                let ident = self.compile_ident(id);
+                let generics = self.compile_generics(generics);
+                let ty = self.compile_type(&const_info.ty);
+
+                let tcr = tcx.typeck_body(*body_id);
+                let body = tcx.hir_body(*body_id);
+                assert!(body.params.is_empty());
+                let expr = self.compile_expr_hir(tcx, tcr, body.value);
+
+                self.outfile.items.push(syn::Item::Const(syn::ItemConst {
+                    attrs,
+                    vis,
+                    const_token: <syn::Token![const]>::default(),
+                    ident,
+                    generics,
+                    colon_token: <syn::Token![:]>::default(),
+                    ty: Box::new(ty),
+                    eq_token: <syn::Token![=]>::default(),
+                    expr: Box::new(expr),
+                    semi_token: <syn::Token![;]>::default(),
+                }));
+            }
+            IK::Fn { ident, sig, generics, body, has_body } => {
+                let attrs = self.compile_attrs(&parsed_info.attrs);
+                let vis = self.compile_vis(&parsed_info.vis);
+                let ident = self.compile_ident(ident);
+                let constness = if sig.header.is_const() { Some(<syn::Token![const]>::default()) } else { None };
+                let asyncness = if sig.header.is_async() { Some(<syn::Token![async]>::default()) } else { None };
+                let abi = match sig.header.abi {
+                    rustc_abi::ExternAbi::Rust => None,
+                    abi => Some(syn::Abi {
+                        extern_token: <syn::Token![extern]>::default(),
+                        name: Some(Self::to_lit_str(abi.as_str())),
+                    }),
+                };
+                let generics = self.compile_generics(generics);
+
+                let rustc_ast::ItemKind::Fn(fn_info) = &parsed_info.kind else {
+                    self.report_mismatched_item(span, "a function");
+                    return;
+                };
+
+                // TODO: Acually handle all the things
+                // A C-variadic function's last parameter is its `...`, which syn keeps apart.
+                let (c_variadic, params) = match fn_info.sig.decl.inputs.split_last() {
+                    Some((last, params)) if matches!(last.ty.kind, rustc_ast::TyKind::CVarArgs) => (Some(last), params),
+                    _ => (None, &fn_info.sig.decl.inputs[..]),
+                };
+                let inputs: syn::punctuated::Punctuated<_, syn::Token![,]> = params.iter().map(|param| {
+                    syn::FnArg::Typed(syn::PatType {
+                        attrs: vec![],
+                        pat: Box::new(self.compile_pat(&param.pat)),
+                        colon_token: <syn::Token![:]>::default(),
+                        ty: Box::new(self.compile_type(&param.ty)),
+                    })
+                }).collect();
+
+                let output = match &fn_info.sig.decl.output {
+                    rustc_ast::FnRetTy::Default(_) => syn::ReturnType::Default,
+                    rustc_ast::FnRetTy::Ty(ty) => syn::ReturnType::Type(<syn::Token![->]>::default(), Box::new(self.compile_type(ty))),
+                };
+
+                let variadic = c_variadic.map(|param| syn::Variadic {
+                    attrs: vec![],
+                    pat: match param.pat.kind {
+                        rustc_ast::PatKind::Missing => None,
+                        _ => Some((Box::new(self.compile_pat(&param.pat)), <syn::Token![:]>::default())),
+                    },
+                    dots: <syn::Token![...]>::default(),
+                    comma: None,
+                });
+
+                if !has_body {
+                    unsupported!(self, span, "functions without bodies");
+                    return;
+                }
+                let tcr = tcx.typeck_body(*body);
+                let body = tcx.hir_body(*body);
+                let rustc_hir::ExprKind::Block(block, _) = &body.value.kind else {
+                    self.report_mismatched_item(span, "a function with a block for a body");
+                    return;
+                };
+                let block = self.compile_block(tcx, tcr, block);
+
+                self.outfile.items.push(syn::Item::Fn(syn::ItemFn {
+                    attrs,
+                    vis,
+                    sig: syn::Signature {
+                        constness,
+                        asyncness,
+                        unsafety: Some(<syn::Token![unsafe]>::default()),
+                        abi,
+                        fn_token: <syn::Token![fn]>::default(),
+                        ident,
+                        generics,
+                        paren_token: syn::token::Paren::default(),
+                        inputs,
+                        variadic,
+                        output,
+                    },
+                    block: Box::new(block),
+                }));
+            }
+            IK::Macro(_id, _def, _kind) => unsupported!(self, span, "macro definitions"),
+            IK::Mod(_id, _mod) => unsupported!(self, span, "modules"),
+            IK::ForeignMod { abi: _, items: _ } => unsupported!(self, span, "`extern` blocks"),
+            IK::GlobalAsm { asm: _, fake_body: _ } => unsupported!(self, span, "`global_asm!`"),
+            IK::TyAlias(_id, _ty, _generics) => unsupported!(self, span, "type aliases"),
+            IK::Enum(_id, _def, _generics) => unsupported!(self, span, "enums"),
+            IK::Struct(_id, _var, _generics) => unsupported!(self, span, "structs"),
+            IK::Union(_id, _var, _generics) => unsupported!(self, span, "unions"),
+            IK::Trait(_is_auto, _safety, _id, _generics, _generic_bounds, _item_refs) => unsupported!(self, span, "traits"),
+            IK::TraitAlias(_id, _generics, _generic_bounds) => unsupported!(self, span, "trait aliases"),
+            IK::Impl(_impl) => unsupported!(self, span, "`impl` blocks"),
+        }
+    }
+
+    /// Removes `#[crust::<name>]` from `attrs`, returning whether it was present.
+    /// These attributes only mean something to crustc, so rustc must never see them.
+    fn take_crust_attr(attrs: &mut rustc_ast::AttrVec, name: &str) -> bool {
+        let len = attrs.len();
+        attrs.retain(|attr| {
+            let path = attr.path();
+            !(path.len() == 2 && path[0].as_str() == "crust" && path[1].as_str() == name)
+        });
+        attrs.len() != len
+    }
+
+    /// Without std there's no runtime to call `main` for us, so the crate is `#![no_main]`
+    /// and we export a C `main` that calls the user's one.
+    fn compile_entry_point(&self) -> Option<syn::Item> {
+        let main = self.parsed_infos.values().find_map(|item| match &item.kind {
+            rustc_ast::ItemKind::Fn(f) if f.ident.name.as_str() == "main" => Some(f),
+            _ => None,
+        })?;
+
+        let call: syn::Expr = match main.sig.decl.inputs.len() {
+            0 => syn::parse_quote! { main() },
+            // `fn main(argv: *const [*const CStr])`
+            1 => syn::parse_quote! {{
+                let args = libc::malloc(argc as usize * core::mem::size_of::<*const core::ffi::CStr>()) as *mut *const core::ffi::CStr;
+                for i in 0..argc as usize {
+                    *args.add(i) = core::ffi::CStr::from_ptr(*argv.add(i));
+                }
+                main(core::ptr::slice_from_raw_parts(args, argc as usize))
+            }},
+            // `fn main(argc: i32, argv: *mut *mut c_char)`
+            _ => syn::parse_quote! { main(argc as _, argv as _) },
+        };
+
+        let call = Self::paren_if_needed(call);
+        let body: syn::Block = match main.sig.decl.output {
+            rustc_ast::FnRetTy::Default(_) => syn::parse_quote! {{
+                #call;
+                0
+            }},
+            rustc_ast::FnRetTy::Ty(_) => syn::parse_quote! {{
+                #call as libc::c_int
+            }},
+        };
+
+        let (argc, argv) = if main.sig.decl.inputs.is_empty() {
+            (quote! { _argc }, quote! { _argv })
+        } else {
+            (quote! { argc }, quote! { argv })
+        };
+
+        Some(syn::parse_quote! {
+            #[export_name = "main"]
+            pub unsafe extern "C" fn __crust_main(#argc: libc::c_int, #argv: *mut *mut libc::c_char) -> libc::c_int #body
+        })
+    }
+
+    /// The precompiled `core` is built with unwinding, so it still references the personality
+    /// routine even though we build with `-C panic=abort`. Nothing will ever call it.
+    fn compile_eh_personality() -> syn::Item {
+        syn::parse_quote! {
+            #[no_mangle]
+            extern "C" fn rust_eh_personality() {}
+        }
+    }
+
+    fn compile_panic_handler(&self) -> syn::Item {
+        let body: syn::Block = match self.panic_handler {
+            PanicHandler::Abort => syn::parse_quote! {{
+                unsafe { libc::abort() }
+            }},
+            PanicHandler::Print => syn::parse_quote! {{
+                unsafe {
+                    if let Some(location) = info.location() {
+                        let file = location.file();
+                        libc::fprintf!(libc::stderr, "panicked at %.*s:%u:%u:\n", file.len() as libc::c_int, file.as_ptr(), location.line(), location.column());
+                    } else {
+                        libc::fprintf!(libc::stderr, "panicked\n");
+                    }
+                    if let Some(message) = info.message().as_str() {
+                        libc::fprintf!(libc::stderr, "%.*s\n", message.len() as libc::c_int, message.as_ptr());
+                    }
+                    libc::abort()
+                }
+            }},
+            PanicHandler::User => {
+                let handler = self.compile_ident(self.user_panic_handler.as_ref().expect("user panic handler should have been found while parsing"));
+                syn::parse_quote! {{
+                    unsafe { #handler(info) }
+                }}
+            }
+        };
+
+        syn::parse_quote! {
+            #[panic_handler]
+            fn __crust_panic_handler(info: &core::panic::PanicInfo) -> ! #body
+        }
+    }
+
+    fn compile_ident(&self, ident: &rustc_span::Ident) -> syn::Ident {
+        syn::Ident::new(&ident.name.to_ident_string(), self.source_span(ident.span))
+    }
+
+    fn compile_use_tree(&self, tree: &rustc_ast::UseTree) -> syn::UseTree {
+        let mut segments: Vec<_> = tree.prefix.segments.iter()
+            .filter(|seg| seg.ident.name != rustc_span::symbol::kw::PathRoot)
+            .map(|seg| self.compile_ident(&seg.ident))
+            .collect();
+
+        let last = match &tree.kind {
+            rustc_ast::UseTreeKind::Simple(rename) => {
+                let ident = segments.pop().expect("simple use trees have a name");
+                match rename {
+                    Some(rename) => syn::UseTree::Rename(syn::UseRename {
+                        ident,
+                        as_token: <syn::Token![as]>::default(),
+                        rename: self.compile_ident(rename),
+                    }),
+                    None => syn::UseTree::Name(syn::UseName { ident }),
+                }
+            }
+            rustc_ast::UseTreeKind::Glob => syn::UseTree::Glob(syn::UseGlob {
+                star_token: <syn::Token![*]>::default(),
+            }),
+            rustc_ast::UseTreeKind::Nested { items, span: _ } => syn::UseTree::Group(syn::UseGroup {
+                brace_token: syn::token::Brace::default(),
+                items: items.iter().map(|(tree, _id)| self.compile_use_tree(tree)).collect(),
+            }),
+        };
+
+        segments.into_iter().rev().fold(last, |tree, ident| syn::UseTree::Path(syn::UsePath {
+            ident,
+            colon2_token: <syn::Token![::]>::default(),
+            tree: Box::new(tree),
+        }))
+    }
+
+    fn compile_attrs(&self, attrs: &rustc_ast::AttrVec) -> Vec<syn::Attribute> {
+        let mut compiled = vec![];
+        for attr in attrs.iter().filter(|attr| !attr.is_doc_comment()) {
+            // Exporting things under their own names is how libraries are used from C.
+            if attr.has_name(rustc_span::sym::no_mangle) && attr.is_word() {
+                compiled.push(syn::parse_quote! { #[no_mangle] });
+                continue;
+            }
+            unsupported!(self, attr.span, "attributes");
+        }
+        compiled
+    }
+
+    fn compile_attrs_hir<'a>(&self, attrs: impl IntoIterator<Item=&'a rustc_hir::Attribute>) -> Vec<syn::Attribute> {
+        for attr in attrs.into_iter().filter(|attr| !attr.is_doc_comment()) {
+            unsupported!(self, attr.span(), "attributes");
+        }
+        vec![]
+    }
+
+    fn compile_vis(&self, vis: &rustc_ast::Visibility) -> syn::Visibility {
+        use rustc_ast::VisibilityKind as VK;
+        match &vis.kind {
+            VK::Public => syn::Visibility::Public(<syn::Token![pub]>::default()),
+            VK::Restricted { path: _, id: _, shorthand: _ } => unsupported!(self, vis.span, syn::Visibility::Inherited, "restricted visibility"),
+            VK::Inherited => syn::Visibility::Public(<syn::Token![pub]>::default()),
+        }
+    }
+
+    fn compile_mutability(&self, mutbl: rustc_hir::Mutability) -> syn::StaticMutability {
+        if matches!(mutbl, rustc_hir::Mutability::Mut) {
+            syn::StaticMutability::Mut(<Token![mut]>::default())
+        } else {
+            syn::StaticMutability::None
+        }
+    }
+
+    fn compile_pat(&self, pat: &rustc_ast::Pat) -> syn::Pat {
+        use rustc_ast::PatKind as PK;
+        match &pat.kind {
+            PK::Missing => syn::Pat::Verbatim(proc_macro2::TokenStream::new()),
+            PK::Wild => syn::Pat::Wild(syn::PatWild {
+                attrs: vec![],
+                underscore_token: <syn::Token![_]>::default(),
+            }),
+            PK::Ident(rustc_ast::BindingMode(_ref, _mut), id, pat) => {
+                let by_ref = if matches!(_ref, rustc_ast::ByRef::Yes(_)) {
+                    Some(<syn::Token![ref]>::default())
+                } else {
+                    None
+                };
+
+                let _mut = if matches!(_mut, rustc_ast::Mutability::Mut) {
+                    Some(<syn::Token![mut]>::default())
+                } else {
+                    None
+                };
+
+                let ident = self.compile_ident(id);
+
+                let subpat = pat.as_ref().map(|pat| (
+                    <syn::Token![@]>::default(),
+                    Box::new(self.compile_pat(pat)),
+                ));
+
+                syn::Pat::Ident(syn::PatIdent {
+                    attrs: vec![],
+                    by_ref,
+                    mutability: _mut,
+                    ident,
+                    subpat,
+                })
+            }
+            PK::Struct(qself, path, fields, rest) => {
+                syn::Pat::Struct(syn::PatStruct {
+                    attrs: vec![],
+                    qself: qself.as_ref().map(|qself| self.compile_qself(qself)),
+                    path: self.compile_path(path),
+                    brace_token: syn::token::Brace::default(),
+                    fields: fields.iter().map(|field| self.compile_field_pat(field)).collect(),
+                    rest: match rest {
+                        rustc_ast::PatFieldsRest::Rest => Some(syn::PatRest { attrs: vec![], dot2_token: <syn::Token![..]>::default() }),
+                        rustc_ast::PatFieldsRest::Recovered(err) => err.raise_fatal(), // TODO: Is this what we want to do?
+                        rustc_ast::PatFieldsRest::None => None,
+                    },
+                })
+            }
+            PK::TupleStruct(qself, path, pats) => {
+                syn::Pat::TupleStruct(syn::PatTupleStruct {
+                    attrs: vec![],
+                    qself: qself.as_ref().map(|qself| self.compile_qself(qself)),
+                    path: self.compile_path(path),
+                    paren_token: syn::token::Paren::default(),
+                    elems: pats.iter().map(|pat| self.compile_pat(pat)).collect(),
+                })
+            }
+            PK::Or(pats) => {
+                syn::Pat::Or(syn::PatOr {
+                    attrs: vec![],
+                    leading_vert: None,
+                    cases: pats.iter().map(|pat| self.compile_pat(pat)).collect(),
+                })
+            }
+            PK::Path(qself, path) => {
+                syn::Pat::Path(syn::PatPath {
+                    attrs: vec![],
+                    qself: qself.as_ref().map(|qself| self.compile_qself(qself)),
+                    path: self.compile_path(path),
+                })
+            }
+            PK::Tuple(pats) => {
+                syn::Pat::Tuple(syn::PatTuple {
+                    attrs: vec![],
+                    paren_token: syn::token::Paren::default(),
+                    elems: pats.iter().map(|pat| self.compile_pat(pat)).collect(),
+                })
+            }
+            PK::Box(pat) => unsupported!(self, pat.span, syn::Pat::Verbatim(proc_macro2::TokenStream::new()), "box patterns"),
+            PK::Deref(_pat) => unsupported!(self, pat.span, syn::Pat::Verbatim(proc_macro2::TokenStream::new()), "deref patterns"),
+            PK::Ref(_pat, _mut) => unsupported!(self, pat.span, syn::Pat::Verbatim(proc_macro2::TokenStream::new()), "reference patterns"),
+            PK::Expr(_expr) => unsupported!(self, pat.span, syn::Pat::Verbatim(proc_macro2::TokenStream::new()), "literal patterns"),
+            PK::Range(start, end, rustc_span::source_map::Spanned { node: limits, .. }) => {
+                syn::Pat::Range(syn::PatRange {
+                    attrs: vec![],
+                    start: start.as_ref().map(|start| Box::new(self.compile_expr(start))),
+                    limits: match limits {
+                        rustc_ast::RangeEnd::Included(_) => syn::RangeLimits::Closed(<syn::Token![..=]>::default()),
+                        rustc_ast::RangeEnd::Excluded => syn::RangeLimits::HalfOpen(<syn::Token![..]>::default()),
+                    },
+                    end: end.as_ref().map(|end| Box::new(self.compile_expr(end))),
+                })
+            }
+            PK::Slice(pats) => {
+                syn::Pat::Slice(syn::PatSlice {
+                    attrs: vec![],
+                    bracket_token: syn::token::Bracket::default(),
+                    elems: pats.iter().map(|pat| self.compile_pat(pat)).collect(),
+                })
+            }
+            PK::Rest => {
+                syn::Pat::Rest(syn::PatRest {
+                    attrs: vec![],
+                    dot2_token: <syn::Token![..]>::default(),
+                })
+            }
+            PK::Never => unsupported!(self, pat.span, syn::Pat::Verbatim(proc_macro2::TokenStream::new()), "never patterns"),
+            PK::Guard(_pat, _expr) => unsupported!(self, pat.span, syn::Pat::Verbatim(proc_macro2::TokenStream::new()), "pattern guards"),
+            PK::Paren(pat) => {
+                syn::Pat::Paren(syn::PatParen {
+                    attrs: vec![],
+                    paren_token: syn::token::Paren::default(),
+                    pat: Box::new(self.compile_pat(pat)),
+                })
+            }
+            PK::MacCall(_call) => unsupported!(self, pat.span, syn::Pat::Verbatim(proc_macro2::TokenStream::new()), "macros in patterns"),
+            PK::Err(err) => err.raise_fatal(),
+        }
+    }
+
+    fn compile_path(&self, path: &rustc_ast::Path) -> syn::Path {
+        let is_root = |seg: &&rustc_ast::PathSegment| seg.ident.name == rustc_span::symbol::kw::PathRoot;
+        syn::Path {
+            leading_colon: path.segments.first().filter(is_root).map(|_| <syn::Token![::]>::default()),
+            segments: path.segments.iter().filter(|seg| !is_root(seg)).map(|seg| {
+                if let Some(args) = &seg.args {
+                    unsupported!(self, args.span(), "generic arguments");
+                }
+                syn::PathSegment {
+                    ident: self.compile_ident(&seg.ident),
+                    arguments: syn::PathArguments::None,
+                }
+            }).collect(),
+        }
+    }
+
+    fn compile_path_hir<'hir, R>(&self, path: &'hir rustc_hir::Path<'hir, R>) -> syn::Path {
+        let is_root = |seg: &&rustc_hir::PathSegment<'hir>| seg.ident.name == rustc_span::symbol::kw::PathRoot;
+        syn::Path {
+            leading_colon: path.segments.first().filter(is_root).map(|_| <syn::Token![::]>::default()),
+            segments: path.segments.iter().filter(|seg| !is_root(seg)).map(|seg| {
+                if let Some(args) = seg.args.filter(|args| !args.is_empty() || !args.constraints.is_empty()) {
+                    unsupported!(self, args.span_ext().unwrap_or(path.span), "generic arguments");
+                }
+                syn::PathSegment {
+                    ident: self.compile_ident(&seg.ident),
+                    arguments: syn::PathArguments::None,
+                }
+            }).collect(),
+        }
+    }
+
+    fn compile_field_pat(&self, pat: &rustc_ast::PatField) -> syn::FieldPat {
+        syn::FieldPat {
+            attrs: self.compile_attrs(&pat.attrs),
+            member: syn::Member::Named(self.compile_ident(&pat.ident)),
+            colon_token: (!pat.is_shorthand).then(<syn::Token![:]>::default),
+            pat: Box::new(self.compile_pat(&pat.pat)),
+        }
+    }
+
+    fn compile_type(&self, ty: &rustc_ast::Ty) -> syn::Type {
+        match &ty.kind {
+            rustc_ast::TyKind::Slice(ty) => syn::Type::Slice(syn::TypeSlice {
+                bracket_token: syn::token::Bracket::default(),
+                elem: Box::new(self.compile_type(ty)),
+            }),
+            rustc_ast::TyKind::Array(ty, _const) => syn::Type::Array(syn::TypeArray {
+                bracket_token: syn::token::Bracket::default(),
+                elem: Box::new(self.compile_type(ty)),
+                semi_token: <syn::Token![;]>::default(),
+                len: self.compile_expr(&_const.value),
+            }),
+            rustc_ast::TyKind::Ptr(mut_ty) => syn::Type::Ptr(syn::TypePtr {
+                star_token: <syn::Token![*]>::default(),
+                const_token: if matches!(mut_ty.mutbl, rustc_ast::Mutability::Not) { Some(<syn::Token![const]>::default()) } else { None },
+                mutability: if matches!(mut_ty.mutbl, rustc_ast::Mutability::Mut) { Some(<syn::Token![mut]>::default()) } else { None },
+                elem: Box::new(self.compile_type(&mut_ty.ty)),
+            }),
+            rustc_ast::TyKind::Ref(_lifetime, mut_ty) => {
+                self.report_reference_type(ty.span, mut_ty);
+                Self::placeholder_type()
+            }
+            rustc_ast::TyKind::PinnedRef(..) => unsupported!(self, ty.span, Self::placeholder_type(), "pinned reference types"),
+            rustc_ast::TyKind::BareFn(_fn_type) => unsupported!(self, ty.span, Self::placeholder_type(), "function pointer types"),
+            rustc_ast::TyKind::UnsafeBinder(_binder) => unsupported!(self, ty.span, Self::placeholder_type(), "unsafe binder types"),
+            rustc_ast::TyKind::Never => syn::Type::Never(syn::TypeNever {
+                bang_token: <syn::Token![!]>::default(),
+            }),
+            rustc_ast::TyKind::Tup(types) => syn::Type::Tuple(syn::TypeTuple {
+                paren_token: syn::token::Paren::default(),
+                elems: types.iter().map(|ty| self.compile_type(ty)).collect(),
+            }),
+            rustc_ast::TyKind::Path(qself, path) => syn::Type::Path(syn::TypePath {
+                qself: qself.as_ref().map(|qself| self.compile_qself(qself)),
+                path: self.compile_path(path),
+            }),
+            rustc_ast::TyKind::TraitObject(_bounds, _syntax) => unsupported!(self, ty.span, Self::placeholder_type(), "trait objects"),
+            rustc_ast::TyKind::ImplTrait(_, _bounds) => unsupported!(self, ty.span, Self::placeholder_type(), "`impl Trait` types"),
+            rustc_ast::TyKind::Paren(ty) => syn::Type::Paren(syn::TypeParen {
+                paren_token: syn::token::Paren::default(),
+                elem: Box::new(self.compile_type(ty)),
+            }),
+            rustc_ast::TyKind::Typeof(_const) => unsupported!(self, ty.span, Self::placeholder_type(), "`typeof` types"),
+            rustc_ast::TyKind::Infer => syn::Type::Infer(syn::TypeInfer {
+                underscore_token: <syn::Token![_]>::default(),
+            }),
+            rustc_ast::TyKind::ImplicitSelf => unsupported!(self, ty.span, Self::placeholder_type(), "`self` parameters"),
+            rustc_ast::TyKind::MacCall(_call) => unsupported!(self, ty.span, Self::placeholder_type(), "macros in types"),
+            rustc_ast::TyKind::CVarArgs => syn::Type::Verbatim(quote!{ ... }.into_token_stream()),
+            rustc_ast::TyKind::Pat(_ty, _ty_pat) => unsupported!(self, ty.span, Self::placeholder_type(), "pattern types"),
+            rustc_ast::TyKind::Dummy => unsupported!(self, ty.span, Self::placeholder_type(), "this type"),
+            rustc_ast::TyKind::Err(err) => err.raise_fatal(),
+        }
+    }
+
+    fn compile_generics<'hir>(&self, generics: &'hir rustc_hir::Generics<'hir>) -> syn::Generics {
+        // Elided lifetimes and `impl Trait` arguments show up here without being written.
+        let written = generics.params.iter().any(|param| !param.is_elided_lifetime() && !param.is_impl_trait());
+        if written || !generics.predicates.is_empty() {
+            unsupported!(self, generics.span, "generics");
+        }
+        syn::Generics::default()
+    }
+
+    /// Only used for constants inside types and patterns, which are plain Rust either way.
+    fn compile_expr(&self, expr: &rustc_ast::Expr) -> syn::Expr {
+        match syn::parse_str(&rustc_ast_pretty::pprust::expr_to_string(expr)) {
+            Ok(expr) => expr,
+            Err(_) => unsupported!(self, expr.span, Self::placeholder_expr(), "this expression"),
+        }
+    }
+
+    fn compile_expr_hir<'tcx, 'hir>(&self, tcx: TyCtxt<'tcx>, tcr: &'tcx TypeckResults<'tcx>, expr: &'hir rustc_hir::Expr<'hir>) -> syn::Expr {
+        if let Some(call_site) = Self::macro_call_site(expr.span) {
+            let mut finder = MacroArgFinder { call_site, args: vec![] };
+            finder.visit_expr(expr);
+            return self.compile_macro_call(tcx, tcr, call_site, finder);
+        }
+
+        let attrs = self.compile_attrs_hir(tcx.hir_attrs(expr.hir_id));
+        match &expr.kind {
+            rustc_hir::ExprKind::ConstBlock(block) => {
+                let block = tcx.hir_body(block.body);
+                let rustc_hir::ExprKind::Block(block, _) = &block.value.kind else {
+                    return unsupported!(self, expr.span, Self::placeholder_expr(), "this `const` block");
+                };
+                let mut _block = self.compile_block(tcx, tcr, block);
+                if let Some(expr) = block.expr {
+                    _block.stmts.push(syn::Stmt::Expr(self.compile_expr_hir(tcx, tcr, expr), None));
+                }
+                syn::Expr::Const(syn::ExprConst {
+                    attrs,
+                    const_token: <syn::Token![const]>::default(),
+                    block: _block,
+                })
+            }
+            rustc_hir::ExprKind::Array(exprs) => syn::Expr::Array(syn::ExprArray {
+                attrs,
+                bracket_token: syn::token::Bracket::default(),
+                elems: exprs.iter().map(|expr| self.compile_expr_hir(tcx, tcr, expr)).collect(),
+            }),
+            rustc_hir::ExprKind::Call(callee, args) => syn::Expr::Call(syn::ExprCall {
+                attrs,
+                func: Box::new(self.compile_expr_hir(tcx, tcr, callee)),
+                paren_token: syn::token::Paren::default(),
+                args: args.iter().map(|arg| self.compile_expr_hir(tcx, tcr, arg)).collect(),
+            }),
+            rustc_hir::ExprKind::MethodCall(_path_segment, _callee, _args, _) => unsupported!(self, expr.span, Self::placeholder_expr(), "method calls"),
+            rustc_hir::ExprKind::Use(_expr, _) => unsupported!(self, expr.span, Self::placeholder_expr(), "`.use` expressions"),
+            rustc_hir::ExprKind::Tup(exprs) => syn::Expr::Tuple(syn::ExprTuple {
+                attrs,
+                paren_token: syn::token::Paren::default(),
+                elems: exprs.iter().map(|expr| self.compile_expr_hir(tcx, tcr, expr)).collect(),
+            }),
+            rustc_hir::ExprKind::Binary(op, lhs, rhs) => syn::Expr::Binary(syn::ExprBinary {
+                attrs,
+                left: Box::new(self.compile_expr_hir(tcx, tcr, lhs)),
+                op: self.compile_binop_hir(op),
+                right: Box::new(self.compile_expr_hir(tcx, tcr, rhs)),
+            }),
+            rustc_hir::ExprKind::Unary(op, expr) => syn::Expr::Unary(syn::ExprUnary {
+                attrs,
+                op: self.compile_unop_hir(*op),
+                expr: Box::new(self.compile_expr_hir(tcx, tcr, expr)),
+            }),
+            rustc_hir::ExprKind::Lit(lit) => {
+                let wrap = matches!(&lit.node, rustc_ast::LitKind::Str(..) | rustc_ast::LitKind::CStr(..));
+                let span = self.source_span(expr.span);
+                let lit = syn::Expr::Lit(syn::ExprLit {
+                    attrs,
+                    lit: match &lit.node {
+                        rustc_ast::LitKind::Str(sym, _) => {
+                            let value = CString::new(sym.as_str()).unwrap_or_else(|_| {
+                                self.report_nul_in_string(expr.span);
+                                CString::default()
+                            });
+                            syn::Lit::CStr(syn::LitCStr::new(&value, span))
+                        }
+                        rustc_ast::LitKind::ByteStr(bytes, _) => syn::Lit::ByteStr(syn::LitByteStr::new(bytes, span)),
+                        rustc_ast::LitKind::CStr(bytes, _) => syn::Lit::CStr(syn::LitCStr::new(
+                            &CString::from_vec_with_nul(bytes.as_ref().to_vec()).expect("Bad C-String"),
+                            span
+                        )),
+                        rustc_ast::LitKind::Byte(value) => Self::spanned_lit(proc_macro2::Literal::byte_character(*value), span),
+                        rustc_ast::LitKind::Char(value) => Self::spanned_lit(proc_macro2::Literal::character(*value), span),
+                        rustc_ast::LitKind::Int(value, ty) => match ty {
+                            rustc_ast::LitIntType::Signed(ty) => syn::Lit::Int(syn::LitInt::new(&format!("{}{}", { value.0 }, ty.name_str()), span)),
+                            rustc_ast::LitIntType::Unsigned(ty) => syn::Lit::Int(syn::LitInt::new(&format!("{}{}", { value.0 }, ty.name_str()), span)),
+                            rustc_ast::LitIntType::Unsuffixed => Self::spanned_lit(proc_macro2::Literal::u128_unsuffixed(value.0), span), // TODO: Handle other types
+                        },
+                        rustc_ast::LitKind::Float(sym, ty) => syn::Lit::Float(syn::LitFloat::new(&match ty {
+                            rustc_ast::LitFloatType::Suffixed(ty) => format!("{}{}", sym.as_str(), ty.name_str()),
+                            rustc_ast::LitFloatType::Unsuffixed => sym.to_string(),
+                        }, span)),
+                        rustc_ast::LitKind::Bool(value) => syn::Lit::Bool(syn::LitBool {
+                            value: *value,
+                            span,
+                        }),
+                        rustc_ast::LitKind::Err(err) => err.raise_fatal(),
+                    },
+                });
+
+                if wrap {
+                    syn::parse_quote! {
+                        (#lit).as_ptr()
+                    }
+                } else {
+                    lit
+                }
+            }
+            rustc_hir::ExprKind::Cast(_expr, _ty) => unsupported!(self, expr.span, Self::placeholder_expr(), "casts"),
+            rustc_hir::ExprKind::Type(_expr, _ty) => unsupported!(self, expr.span, Self::placeholder_expr(), "type ascription"),
+            rustc_hir::ExprKind::DropTemps(_expr) => unsupported!(self, expr.span, Self::placeholder_expr(), "this expression"),
+            rustc_hir::ExprKind::Let(_let) => unsupported!(self, expr.span, Self::placeholder_expr(), "`let` expressions"),
+            rustc_hir::ExprKind::If(_cond, _then, _else) => unsupported!(self, expr.span, Self::placeholder_expr(), "`if` expressions"),
+            rustc_hir::ExprKind::Loop(_cond, _label, _source, _) => unsupported!(self, expr.span, Self::placeholder_expr(), "loops"),
+            rustc_hir::ExprKind::Match(_cond, _arms, _source) => unsupported!(self, expr.span, Self::placeholder_expr(), "`match` expressions"),
+            rustc_hir::ExprKind::Closure(_closure) => unsupported!(self, expr.span, Self::placeholder_expr(), "closures"),
+            rustc_hir::ExprKind::Block(_block, _label) => unsupported!(self, expr.span, Self::placeholder_expr(), "block expressions"),
+            rustc_hir::ExprKind::Assign(_lhs, _rhs, _) => unsupported!(self, expr.span, Self::placeholder_expr(), "assignments"),
+            rustc_hir::ExprKind::AssignOp(_op, _lhs, _rhs) => unsupported!(self, expr.span, Self::placeholder_expr(), "compound assignments"),
+            rustc_hir::ExprKind::Field(_expr, _id) => unsupported!(self, expr.span, Self::placeholder_expr(), "field accesses"),
+            rustc_hir::ExprKind::Index(_expr, _idx, _) => unsupported!(self, expr.span, Self::placeholder_expr(), "indexing"),
+            rustc_hir::ExprKind::Path(qpath) => syn::Expr::Path(syn::ExprPath {
+                attrs,
+                qself: None,
+                path: match qpath {
+                    rustc_hir::QPath::Resolved(None, path) => self.compile_path_hir(path),
+                    rustc_hir::QPath::Resolved(Some(_), _) | rustc_hir::QPath::TypeRelative(..) => {
+                        unsupported!(self, expr.span, Self::placeholder_path(), "associated items")
+                    }
+                    rustc_hir::QPath::LangItem(..) => unsupported!(self, expr.span, Self::placeholder_path(), "this expression"),
+                },
+            }),
+            rustc_hir::ExprKind::AddrOf(_borrow_kind, _mutbl, _expr) => unsupported!(self, expr.span, Self::placeholder_expr(), "borrows"),
+            rustc_hir::ExprKind::Break(_dst, _expr) => unsupported!(self, expr.span, Self::placeholder_expr(), "`break`"),
+            rustc_hir::ExprKind::Continue(_dst) => unsupported!(self, expr.span, Self::placeholder_expr(), "`continue`"),
+            rustc_hir::ExprKind::Ret(_expr) => unsupported!(self, expr.span, Self::placeholder_expr(), "`return`"),
+            rustc_hir::ExprKind::Become(_expr) => unsupported!(self, expr.span, Self::placeholder_expr(), "`become`"),
+            rustc_hir::ExprKind::InlineAsm(_asm) => unsupported!(self, expr.span, Self::placeholder_expr(), "inline assembly"),
+            rustc_hir::ExprKind::OffsetOf(_ty, _ids) => unsupported!(self, expr.span, Self::placeholder_expr(), "`offset_of!`"),
+            rustc_hir::ExprKind::Struct(_qpath, _fields, _tail) => unsupported!(self, expr.span, Self::placeholder_expr(), "struct expressions"),
+            rustc_hir::ExprKind::Repeat(_expr, _const_arg) => unsupported!(self, expr.span, Self::placeholder_expr(), "array repeat expressions"),
+            rustc_hir::ExprKind::Yield(_expr, _source) => unsupported!(self, expr.span, Self::placeholder_expr(), "`yield`"),
+            rustc_hir::ExprKind::UnsafeBinderCast(_kind, _expr, _ty) => unsupported!(self, expr.span, Self::placeholder_expr(), "unsafe binder casts"),
+            rustc_hir::ExprKind::Err(err) => err.raise_fatal(),
+        }
+    }
+
+    fn compile_stmt<'tcx, 'hir>(&self, tcx: TyCtxt<'tcx>, tcr: &'tcx TypeckResults<'tcx>, stmt: &'hir rustc_hir::Stmt<'hir>) -> syn::Stmt {
+        match &stmt.kind {
+            rustc_hir::StmtKind::Expr(expr) => syn::Stmt::Expr(self.compile_expr_hir(tcx, tcr, expr), None),
+            rustc_hir::StmtKind::Semi(expr) => syn::Stmt::Expr(self.compile_expr_hir(tcx, tcr, expr), Some(<syn::Token![;]>::default())),
+            rustc_hir::StmtKind::Let(_let) => unsupported!(self, stmt.span, syn::Stmt::Expr(Self::placeholder_expr(), None), "`let` statements"),
+            rustc_hir::StmtKind::Item(_item) => unsupported!(self, stmt.span, syn::Stmt::Expr(Self::placeholder_expr(), None), "items inside functions"),
+        }
+    }
+
+    fn compile_block<'tcx, 'hir>(&self, tcx: TyCtxt<'tcx>, tcr: &'tcx TypeckResults<'tcx>, block: &'hir rustc_hir::Block<'hir>) -> syn::Block {
+        let mut stmts = vec![];
+        let mut last_call_site = None;
+        for stmt in block.stmts {
+            // A single macro call can expand to several statements, but we only want it once.
+            let Some(call_site) = Self::macro_call_site(stmt.span) else {
+                last_call_site = None;
+                stmts.push(self.compile_stmt(tcx, tcr, stmt));
+                continue;
+            };
+            if last_call_site == Some(call_site) {
+                continue;
+            }
+            last_call_site = Some(call_site);
+
+            let mut finder = MacroArgFinder { call_site, args: vec![] };
+            finder.visit_stmt(stmt);
+            let expr = self.compile_macro_call(tcx, tcr, call_site, finder);
+            stmts.push(syn::Stmt::Expr(expr, Some(<syn::Token![;]>::default())));
+        }
+
+        if let Some(expr) = block.expr {
+            let expr = self.compile_expr_hir(tcx, tcr, expr);
+            stmts.push(syn::Stmt::Expr(expr, None));
+        }
+
+        syn::Block { brace_token: syn::token::Brace::default(), stmts }
+    }
+
+    /// If `span` came from expanding a bang macro, returns the span of the outermost call.
+    fn macro_call_site(mut span: rustc_span::Span) -> Option<rustc_span::Span> {
+        let mut call_site = None;
+        while span.from_expansion() {
+            let data = span.ctxt().outer_expn_data();
+            if let rustc_span::hygiene::ExpnKind::Macro(rustc_span::hygiene::MacroKind::Bang, _) = data.kind {
+                call_site = Some(data.call_site);
+            }
+            span = data.call_site;
+        }
+        call_site
+    }
+
+    /// Re-emits a macro call the way it was written, except for `print!` and friends,
+    /// which are lowered to printf.
+    fn compile_macro_call<'tcx, 'hir>(&self, tcx: TyCtxt<'tcx>, tcr: &'tcx TypeckResults<'tcx>, call_site: rustc_span::Span, args: MacroArgFinder<'hir>) -> syn::Expr {
+        let Some(mac) = self.macro_calls.get(&call_site.lo()) else {
+            return unsupported!(self, call_site, Self::placeholder_expr(), "macro calls written by other macros");
+        };
+
+        if let [seg] = mac.path.segments.as_slice() {
+            if let name @ ("print" | "println" | "eprint" | "eprintln") = seg.ident.name.as_str() {
+                return self.compile_print_macro(tcx, tcr, name, mac, args);
+            }
+        }
+
+        let is_libc = match mac.path.segments.as_slice() {
+            [_] => true,
+            [krate, _] => krate.ident.name.as_str() == "libc",
+            _ => false,
+        };
+        if is_libc {
+            let fmt_index = match mac.path.segments.last().expect("paths have segments").ident.name.as_str() {
+                "printf" => Some(0),
+                "fprintf" => Some(1),
+                "snprintf" => Some(2),
+                _ => None,
+            };
+            if let Some(fmt_index) = fmt_index {
+                return self.compile_printf_macro(tcx, tcr, fmt_index, mac, args);
+            }
+        }
+
+        self.compile_macro_verbatim(mac, rustc_ast_pretty::pprust::tts_to_string(&mac.args.tokens))
+    }
+
+    fn compile_macro_verbatim(&self, mac: &rustc_ast::MacCall, tokens: String) -> syn::Expr {
+        syn::Expr::Macro(syn::ExprMacro {
+            attrs: vec![],
+            mac: syn::Macro {
+                path: self.compile_path(&mac.path),
+                bang_token: <syn::Token![!]>::default(),
+                delimiter: match mac.args.delim {
+                    rustc_ast::token::Delimiter::Brace => syn::MacroDelimiter::Brace(syn::token::Brace::default()),
+                    rustc_ast::token::Delimiter::Bracket => syn::MacroDelimiter::Bracket(syn::token::Bracket::default()),
+                    _ => syn::MacroDelimiter::Paren(syn::token::Paren::default()),
+                },
+                tokens: proc_macro2::TokenStream::from_str(&tokens).expect("tokens from rustc should lex"),
+            },
+        })
+    }
+
+    /// Splits a macro's arguments on top-level commas.
+    fn split_macro_args(tokens: &rustc_ast::tokenstream::TokenStream) -> Vec<Vec<rustc_ast::tokenstream::TokenTree>> {
+        use rustc_ast::tokenstream::TokenTree;
+
+        let mut args = vec![vec![]];
+        for tree in tokens.iter() {
+            match tree {
+                TokenTree::Token(token, _) if token.kind == rustc_ast::token::TokenKind::Comma => args.push(vec![]),
+                tree => args.last_mut().expect("there's always a last argument").push(tree.clone()),
+            }
+        }
+        if args.last().is_some_and(|arg| arg.is_empty()) {
+            args.pop();
+        }
+        args
+    }
+
+    /// The span of an argument [`split_macro_args`](Self::split_macro_args) found, or `None`
+    /// for an empty one, as in `f!(a,, b)`. rustc reports those itself.
+    fn tokens_span(tokens: &[rustc_ast::tokenstream::TokenTree]) -> Option<rustc_span::Span> {
+        Some(tokens.first()?.span().to(tokens.last()?.span()))
+    }
+
+    /// Checks the arguments of `printf!`, `fprintf!` and `snprintf!` against their format
+    /// string, inserting casts where the fix is obvious and lossless.
+    fn compile_printf_macro<'tcx, 'hir>(&self, tcx: TyCtxt<'tcx>, tcr: &'tcx TypeckResults<'tcx>, fmt_index: usize, mac: &rustc_ast::MacCall, args: MacroArgFinder<'hir>) -> syn::Expr {
+        use rustc_ast::tokenstream::{TokenStream, TokenTree};
+
+        let verbatim = || self.compile_macro_verbatim(mac, rustc_ast_pretty::pprust::tts_to_string(&mac.args.tokens));
+
+        let written_args = Self::split_macro_args(&mac.args.tokens);
+        let Some(fmt_tokens) = written_args.get(fmt_index) else {
+            // libc's macros will complain about this themselves.
+            return verbatim();
+        };
+
+        let fmt = match fmt_tokens.as_slice() {
+            [TokenTree::Token(token, _)] => match token.kind {
+                rustc_ast::token::TokenKind::Literal(lit) => match lit.kind {
+                    rustc_ast::token::LitKind::Str => Some((lit.symbol, 1, token.span)),
+                    rustc_ast::token::LitKind::StrRaw(hashes) => Some((lit.symbol, 2 + hashes as u32, token.span)),
+                    rustc_ast::token::LitKind::CStr | rustc_ast::token::LitKind::CStrRaw(_) => {
+                        self.report_bad_format(token.span, "expected a plain string literal, which libc adds the NUL to");
+                        return verbatim();
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some((fmt, quote_len, fmt_span)) = fmt else {
+            self.report_bad_format(Self::tokens_span(fmt_tokens).unwrap_or(mac.span()), "expected a string literal");
+            return verbatim();
+        };
+        let fmt_span_of = |range: std::ops::Range<usize>| {
+            let start = fmt_span.lo() + rustc_span::BytePos(quote_len + range.start as u32);
+            let end = fmt_span.lo() + rustc_span::BytePos(quote_len + range.end as u32);
+            fmt_span.with_lo(start).with_hi(end)
+        };
+
+        let conversions = match printf::parse_printf_format(fmt.as_str()) {
+            Ok(conversions) => conversions,
+            Err((range, err)) => {
+                self.report_bad_format(fmt_span_of(range), &err);
+                return verbatim();
+            }
+        };
+
+        let target = printf::CTarget::new(tcx);
+        let mut expected = vec![];
+        for conversion in &conversions {
+            let star = printf::Expected::Int { bits: target.int_bits, c_type: "c_int" };
+            expected.extend(std::iter::repeat_n((star, conversion), conversion.stars));
+            match conversion.expected(target) {
+                Ok(arg) => expected.push((arg, conversion)),
+                Err(err) => {
+                    self.report_bad_format(fmt_span_of(conversion.range.clone()), &err);
+                    return verbatim();
+                }
+            }
+        }
+
+        let printf_args = &written_args[fmt_index + 1..];
+        if expected.len() != printf_args.len() {
+            self.report_bad_format(fmt_span, &format!("{} conversions but {} arguments", expected.len(), printf_args.len()));
+            return verbatim();
+        }
+
+        let mut fixes = vec![None; written_args.len()];
+        let mut failed = false;
+        for (i, ((expected, conversion), written)) in expected.iter().zip(printf_args).enumerate() {
+            let Some(written_span) = Self::tokens_span(written) else {
+                return verbatim();
+            };
+            let Some(arg) = args.args.iter().find(|arg| written_span.contains(arg.span)) else {
+                continue;
+            };
+
+            let ty = tcr.expr_ty(arg);
+            let is_str_literal = matches!(&arg.kind, rustc_hir::ExprKind::Lit(lit) if matches!(lit.node, rustc_ast::LitKind::Str(..)));
+            let printf_arg = printf::classify(tcx, ty, is_str_literal);
+            match printf::check_arg(*expected, printf_arg, target) {
+                Ok(fix) => fixes[fmt_index + 1 + i] = if is_str_literal { Some(printf::Fix::CStrLiteral) } else { fix },
+                Err(help) => {
+                    let spec = &fmt.as_str()[conversion.range.clone()];
+                    self.report_bad_printf_arg(
+                        written_span,
+                        &format!("`{spec}` expects {}, found `{ty}`", expected.describe()),
+                        fmt_span_of(conversion.range.clone()),
+                        help.as_deref(),
+                    );
+                    failed = true;
+                }
+            }
+        }
+
+        if failed || fixes.iter().all(Option::is_none) {
+            return verbatim();
+        }
+
+        let tokens = written_args.into_iter().zip(fixes).map(|(written, fix)| {
+            let written = rustc_ast_pretty::pprust::tts_to_string(&TokenStream::new(written));
+            match fix {
+                None => written,
+                Some(printf::Fix::Cast(ty)) if ty.starts_with("c_") => format!("({written}) as libc::{ty}"),
+                Some(printf::Fix::Cast(ty)) => format!("({written}) as {ty}"),
+                Some(printf::Fix::CStrAsPtr) => format!("(*({written})).as_ptr()"),
+                Some(printf::Fix::CStrLiteral) => format!("c{written}.as_ptr()"),
+            }
+        }).collect::<Vec<_>>().join(", ");
+        self.compile_macro_verbatim(mac, tokens)
+    }
+
+    /// Lowers `print!("x = {}", x)` to `libc::printf!("x = %d", x)`, picking each
+    /// conversion from the argument's type.
+    fn compile_print_macro<'tcx, 'hir>(&self, tcx: TyCtxt<'tcx>, tcr: &'tcx TypeckResults<'tcx>, name: &str, mac: &rustc_ast::MacCall, args: MacroArgFinder<'hir>) -> syn::Expr {
+        use rustc_ast::tokenstream::TokenTree;
+
+        let unit = syn::Expr::Tuple(syn::ExprTuple {
+            attrs: vec![],
+            paren_token: syn::token::Paren::default(),
+            elems: syn::punctuated::Punctuated::new(),
+        });
+
+        let fmt = match mac.args.tokens.iter().next() {
+            Some(TokenTree::Token(token, _)) => match token.kind {
+                rustc_ast::token::TokenKind::Literal(lit) => match rustc_ast::LitKind::from_token_lit(lit) {
+                    Ok(rustc_ast::LitKind::Str(fmt, _)) => Some((fmt, token.span)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some((fmt, fmt_span)) = fmt else {
+            self.report_bad_format(mac.span(), "expected a string literal as the first argument");
+            return unit;
+        };
+
+        let pieces = match printf::parse_rust_format(fmt.as_str()) {
+            Ok(pieces) => pieces,
+            Err(err) => {
+                self.report_bad_format(fmt_span, &err);
+                return unit;
+            }
+        };
+
+        // Split the written arguments on top-level commas so each placeholder can be matched
+        // with its argument, even if rustc inlined a literal argument into the format string.
+        let written_args = &Self::split_macro_args(&mac.args.tokens)[1..];
+
+        let placeholders = pieces.iter().filter(|piece| matches!(piece, printf::Piece::Placeholder(_))).count();
+        if placeholders != written_args.len() {
+            self.report_bad_format(fmt_span, &format!("{placeholders} placeholders but {} arguments", written_args.len()));
+            return unit;
+        }
+
+        let mut printf_fmt = String::new();
+        let mut printf_args: Vec<syn::Expr> = vec![];
+        let mut written_args = written_args.iter();
+        for piece in &pieces {
+            let spec = match piece {
+                printf::Piece::Lit(lit) => {
+                    printf_fmt.push_str(&printf::escape_printf(lit));
+                    continue;
+                }
+                printf::Piece::Placeholder(spec) => spec,
+            };
+
+            let written = written_args.next().expect("placeholders and arguments were counted");
+            let Some(written_span) = Self::tokens_span(written) else {
+                return unit;
+            };
+            let Some(arg) = args.args.iter().find(|arg| written_span.contains(arg.span)) else {
+                // rustc inlines plain literal arguments into the format string, so do the same.
+                let lit = match written.as_slice() {
+                    [TokenTree::Token(token, _)] => match token.kind {
+                        rustc_ast::token::TokenKind::Literal(lit) => rustc_ast::LitKind::from_token_lit(lit).ok(),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                match lit {
+                    Some(rustc_ast::LitKind::Str(sym, _)) => printf_fmt.push_str(&printf::escape_printf(sym.as_str())),
+                    Some(rustc_ast::LitKind::Int(value, _)) => printf_fmt.push_str(&{ value.0 }.to_string()),
+                    _ => {
+                        self.report_bad_format(written_span, "couldn't find this argument after expansion");
+                        return unit;
+                    }
+                }
+                continue;
+            };
+
+            let is_str_literal = matches!(&arg.kind, rustc_hir::ExprKind::Lit(lit) if matches!(lit.node, rustc_ast::LitKind::Str(..)));
+            let ty = tcr.expr_ty(arg);
+            let Some(printf_arg) = printf::classify(tcx, ty, is_str_literal) else {
+                self.report_bad_format(arg.span, &format!("`{ty}` can't be printed with printf"));
+                return unit;
+            };
+
+            match printf::printf_conversion(spec, printf_arg.class) {
+                Ok(conversion) => printf_fmt.push_str(&conversion),
+                Err(err) => {
+                    self.report_bad_format(arg.span, &err);
+                    return unit;
+                }
+            }
+
+            let expr = Self::paren_if_needed(self.compile_expr_hir(tcx, tcr, arg));
+            printf_args.push(match printf_arg.class {
+                printf::PrintfClass::Bool => syn::parse_quote! { (if #expr { c"true" } else { c"false" }).as_ptr() },
+                printf::PrintfClass::CStr => syn::parse_quote! { (*#expr).as_ptr() },
+                class if printf_arg.promote => {
+                    let ty = syn::Ident::new(class.promoted_type(), proc_macro2::Span::call_site());
+                    syn::parse_quote! { #expr as libc::#ty }
+                }
+                _ => expr,
+            });
+        }
+
+        if name.ends_with("ln") {
+            printf_fmt.push('\n');
+        }
+        let printf_fmt = Self::to_lit_str(printf_fmt);
+        // Spanned like the macro it replaces, so the call can be traced back to it.
+        let libc = syn::Ident::new("libc", self.source_span(mac.path.span));
+
+        if name.starts_with('e') {
+            syn::parse_quote! { #libc::fprintf!(libc::stderr, #printf_fmt #(, #printf_args)*) }
+        } else {
+            syn::parse_quote! { #libc::printf!(#printf_fmt #(, #printf_args)*) }
+        }
+    }
+
+    /// Stands in, as `()`, for an expression we couldn't compile. Only ever built after an
+    /// error was reported, so it never reaches a generated file.
+    fn placeholder_expr() -> syn::Expr {
+        syn::Expr::Tuple(syn::ExprTuple {
+            attrs: vec![],
+            paren_token: syn::token::Paren::default(),
+            elems: syn::punctuated::Punctuated::new(),
+        })
+    }
+
+    /// Like [`Self::placeholder_expr`], for types.
+    fn placeholder_type() -> syn::Type {
+        syn::Type::Never(syn::TypeNever {
+            bang_token: <syn::Token![!]>::default(),
+        })
+    }
+
+    /// Like [`Self::placeholder_expr`], for paths.
+    fn placeholder_path() -> syn::Path {
+        syn::Path {
+            leading_colon: None,
+            segments: syn::punctuated::Punctuated::new(),
+        }
+    }
+
+    /// Wraps `expr` in parentheses unless it already binds tighter than a cast or method call.
+    fn paren_if_needed(expr: syn::Expr) -> syn::Expr {
+        match expr {
+            syn::Expr::Path(_) | syn::Expr::Lit(_) | syn::Expr::Call(_) | syn::Expr::MethodCall(_)
+            | syn::Expr::Field(_) | syn::Expr::Index(_) | syn::Expr::Paren(_) | syn::Expr::Macro(_) => expr,
+            expr => syn::Expr::Paren(syn::ExprParen {
+                attrs: vec![],
+                paren_token: syn::token::Paren::default(),
+                expr: Box::new(expr),
+            }),
+        }
+    }
+
+    fn compile_qself(&self, qself: &rustc_ast::QSelf) -> syn::QSelf {
+        syn::QSelf {
+            lt_token: <syn::Token![<]>::default(),
+            ty: Box::new(self.compile_type(&qself.ty)),
+            position: qself.position,
+            as_token: (qself.position > 0).then(<syn::Token![as]>::default),
+            gt_token: <syn::Token![>]>::default(),
+        }
+    }
+
+    fn compile_unop_hir(&self, unop: rustc_hir::UnOp) -> syn::UnOp {
+        match unop {
+            rustc_hir::UnOp::Deref => syn::UnOp::Deref(<syn::Token![*]>::default()),
+            rustc_hir::UnOp::Not => syn::UnOp::Not(<syn::Token![!]>::default()),
+            rustc_hir::UnOp::Neg => syn::UnOp::Neg(<syn::Token![-]>::default()),
+        }
+    }
+
+    fn compile_binop_hir(&self, binop: &rustc_hir::BinOp) -> syn::BinOp {
+        match &binop.node {
+            rustc_hir::BinOpKind::Add => syn::BinOp::Add(<syn::Token![+]>::default()),
+            rustc_hir::BinOpKind::Sub => syn::BinOp::Sub(<syn::Token![-]>::default()),
+            rustc_hir::BinOpKind::Mul => syn::BinOp::Mul(<syn::Token![*]>::default()),
+            rustc_hir::BinOpKind::Div => syn::BinOp::Div(<syn::Token![/]>::default()),
+            rustc_hir::BinOpKind::Rem => syn::BinOp::Rem(<syn::Token![%]>::default()),
+            rustc_hir::BinOpKind::And => syn::BinOp::And(<syn::Token![&&]>::default()),
+            rustc_hir::BinOpKind::Or => syn::BinOp::Or(<syn::Token![||]>::default()),
+            rustc_hir::BinOpKind::BitXor => syn::BinOp::BitXor(<syn::Token![^]>::default()),
+            rustc_hir::BinOpKind::BitAnd => syn::BinOp::BitAnd(<syn::Token![&]>::default()),
+            rustc_hir::BinOpKind::BitOr => syn::BinOp::BitOr(<syn::Token![|]>::default()),
+            rustc_hir::BinOpKind::Shl => syn::BinOp::Shl(<syn::Token![<<]>::default()),
+            rustc_hir::BinOpKind::Shr => syn::BinOp::Shr(<syn::Token![>>]>::default()),
+            rustc_hir::BinOpKind::Eq => syn::BinOp::Eq(<syn::Token![==]>::default()),
+            rustc_hir::BinOpKind::Lt => syn::BinOp::Lt(<syn::Token![<]>::default()),
+            rustc_hir::BinOpKind::Le => syn::BinOp::Le(<syn::Token![<=]>::default()),
+            rustc_hir::BinOpKind::Ne => syn::BinOp::Ne(<syn::Token![!=]>::default()),
+            rustc_hir::BinOpKind::Ge => syn::BinOp::Ge(<syn::Token![>=]>::default()),
+            rustc_hir::BinOpKind::Gt => syn::BinOp::Gt(<syn::Token![>]>::default()),
+        }
+    }
+
+    fn spanned_lit(mut lit: proc_macro2::Literal, span: proc_macro2::Span) -> syn::Lit {
+        lit.set_span(span);
+        syn::Lit::new(lit)
+    }
+
+    fn to_lit_str(s: impl AsRef<str>) -> syn::LitStr {
+        syn::LitStr::new(s.as_ref(), proc_macro2::Span::call_site())
+    }
+}
+
+impl CrustCompiler {
+    /// What rustc calls the source.
+    fn rustc_filename(&self) -> FileName {
+        FileName::Real(RealFileName::LocalPath(PathBuf::from(&self.source_filename)))
+    }
+
+    /// Where `span` is in the source, if it's there at all.
+    fn range(&self, span: rustc_span::Span) -> Option<Range<usize>> {
+        diagnostics::range(self.source_map.as_ref()?, &self.rustc_filename(), span)
+    }
+
+    /// The span of the source token `span` starts at, for generated tokens to carry.
+    fn source_span(&self, span: rustc_span::Span) -> proc_macro2::Span {
+        self.source_spans.span_at(self.range(span).map(|range| range.start))
+    }
+
+    fn report_unsupported(&self, span: rustc_span::Span, what: &str) {
+        self.diagnostics.emit(
+            Diagnostic::error("unsupported by crustc")
+                .code(error_codes::C0003)
+                .label_or_note(self.range(span), format!("crustc doesn't support {what} yet")),
+        );
+    }
+
+    /// The item at `span` was parsed as one kind of item, but rustc lowered it to another.
+    /// That's crustc's bug, not the program's, but the item still can't be translated.
+    fn report_mismatched_item(&self, span: rustc_span::Span, expected: &str) {
+        self.diagnostics.emit(
+            Diagnostic::error("crustc couldn't translate this item")
+                .label_or_note(self.range(span), format!("expected this to be {expected} after expansion"))
+                .note("this is a bug in crustc"),
+        );
+    }
+
+    fn report_reference_type(&self, span: rustc_span::Span, referent: &rustc_ast::MutTy) {
+        let pointer = match referent.mutbl {
+            rustc_ast::Mutability::Not => "*const",
+            rustc_ast::Mutability::Mut => "*mut",
+        };
+        let referent = match self.range(referent.ty.span) {
+            Some(range) => self.source[range].to_string(),
+            None => rustc_ast_pretty::pprust::ty_to_string(&referent.ty),
+        };
+
+        let mut diagnostic = Diagnostic::error("reference type used")
+            .code(error_codes::C0001)
+            .label_or_note(self.range(span), "reference types are not allowed in crust");
+        if let Some(range) = self.range(span) {
+            diagnostic = diagnostic.suggestion(range, format!("{pointer} {referent}"), "try using a pointer");
+        }
+        self.diagnostics.emit(diagnostic);
+    }
+
+    fn report_std_usage(&self, usage: &StdUsage) {
+        self.diagnostics.emit(
+            Diagnostic::error("std used")
+                .code(error_codes::C0002)
+                .label_or_note(self.range(usage.span), &usage.label)
+                .help(usage.help),
+        );
+    }
+
+    fn report_bad_format(&self, span: rustc_span::Span, label: &str) {
+        self.diagnostics.emit(
+            Diagnostic::error("invalid format string")
+                .code(error_codes::C0004)
+                .label_or_note(self.range(span), label),
+        );
+    }
+
+    fn report_bad_printf_arg(&self, arg_span: rustc_span::Span, label: &str, conversion_span: rustc_span::Span, help: Option<&str>) {
+        let mut diagnostic = Diagnostic::error("mismatched printf argument")
+            .code(error_codes::C0005)
+            .label_or_note(self.range(arg_span), label);
+        if let Some(range) = self.range(conversion_span) {
+            diagnostic = diagnostic.secondary_label(range, "conversion specified here");
+        }
+        if let Some(help) = help {
+            diagnostic = diagnostic.help(help);
+        }
+        self.diagnostics.emit(diagnostic);
+    }
+
+    fn report_nul_in_string(&self, span: rustc_span::Span) {
+        self.diagnostics.emit(
+            Diagnostic::error("NUL byte in a string literal")
+                .code(error_codes::C0009)
+                .label_or_note(self.range(span), "this string would end at its first NUL in C")
+                .help("split the string in two, or use a byte string and a length"),
+        );
+    }
+
+    fn report_duplicate_panic_handler(&self, span: rustc_span::Span) {
+        self.diagnostics.emit(
+            Diagnostic::error("multiple panic handlers")
+                .code(error_codes::C0006)
+                .label_or_note(self.range(span), "second `#[crust::panic_handler]` function here")
+                .help("only one function may be marked `#[crust::panic_handler]`"),
+        );
+    }
+
+    fn report_missing_panic_handler(&self) {
+        self.diagnostics.emit(
+            Diagnostic::error("`--panic-handler=user` given but no panic handler was found")
+                .code(error_codes::C0007)
+                .help("mark a function `fn(*const core::panic::PanicInfo) -> !` with `#[crust::panic_handler]`"),
+        );
+    }
+
+    /// Writes out the diagnostics emitted so far.
+    fn flush_diagnostics(&self) {
+        self.diagnostics.flush(&self.source, Some(&self.source_filename));
+    }
+}
@@ -0,0 +1,729 @@
+//! Collecting the errors and warnings crustc reports.
+//!
+//! Everything crustc has to say about a Crust file goes through [`Diagnostics`] rather than
+//! being printed where it's found, so that we know at the end whether anything went wrong,
+//! and so every diagnostic ends up on stderr in the same shape. When crustc is used as a
+//! library they're kept instead, along with rustc's own, for [`crate::transpile`] to return.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    fmt::Write,
+    io::{self, BufWriter, IsTerminal},
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use rustc_errors::{
+    emitter::{stderr_destination, DynEmitter, Emitter, HumanEmitter, HumanReadableErrorType},
+    json::JsonEmitter,
+    registry::Registry,
+    translation::{to_fluent_args, FluentArgs, Translate},
+    ColorConfig, DiagInner, DiagMessage, FluentBundle, LazyFallbackBundle, Suggestions,
+};
+use rustc_session::parse::ParseSess;
+use rustc_span::{source_map::SourceMap, FileName};
+
+use crate::error_codes;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl Level {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FooterKind {
+    Help,
+    Note,
+}
+
+impl FooterKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Help => "help",
+            Self::Note => "note",
+        }
+    }
+}
+
+/// How diagnostics are written, chosen with `--error-format`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ErrorFormat {
+    #[default]
+    Human,
+    /// One JSON object per line, in the shape rustc's `--error-format=json` uses.
+    Json,
+}
+
+impl ErrorFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Human => "human",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Label {
+    pub span: Range<usize>,
+    pub message: String,
+    /// Primary labels point at the problem; secondary ones at context around it.
+    pub primary: bool,
+}
+
+/// A replacement for some source text that fixes the problem.
+pub struct Suggestion {
+    pub span: Range<usize>,
+    pub replacement: String,
+    pub message: String,
+}
+
+pub struct Diagnostic {
+    pub level: Level,
+    /// One of [`error_codes`] for the diagnostics that are about Crust's own rules, or
+    /// rustc's code for one of its diagnostics.
+    pub code: Option<String>,
+    pub title: String,
+    pub labels: Vec<Label>,
+    pub footers: Vec<(FooterKind, String)>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    pub fn error(title: impl Into<String>) -> Self {
+        Self::new(Level::Error, title.into())
+    }
+
+    pub fn warning(title: impl Into<String>) -> Self {
+        Self::new(Level::Warning, title.into())
+    }
+
+    fn new(level: Level, title: String) -> Self {
+        Self { level, code: None, title, labels: vec![], footers: vec![], suggestions: vec![] }
+    }
+
+    pub fn code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn label(mut self, span: Range<usize>, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into(), primary: true });
+        self
+    }
+
+    /// A label at `span` if it's in the source, or else a note saying the same thing.
+    pub fn label_or_note(self, span: Option<Range<usize>>, message: impl Into<String>) -> Self {
+        match span {
+            Some(span) => self.label(span, message),
+            None => self.note(message),
+        }
+    }
+
+    pub fn secondary_label(mut self, span: Range<usize>, message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into(), primary: false });
+        self
+    }
+
+    pub fn help(mut self, message: impl Into<String>) -> Self {
+        self.footers.push((FooterKind::Help, message.into()));
+        self
+    }
+
+    pub fn note(mut self, message: impl Into<String>) -> Self {
+        self.footers.push((FooterKind::Note, message.into()));
+        self
+    }
+
+    pub fn suggestion(mut self, span: Range<usize>, replacement: impl Into<String>, message: impl Into<String>) -> Self {
+        self.suggestions.push(Suggestion { span, replacement: replacement.into(), message: message.into() });
+        self
+    }
+
+    /// Renders the diagnostic for `format`. Label spans index into `source`, which is the
+    /// file `origin`, or the command line if there's no origin.
+    pub fn render(&self, format: ErrorFormat, source: &str, origin: Option<&str>) -> String {
+        match format {
+            // Like rustc, only color what a person is going to read on a terminal.
+            ErrorFormat::Human if io::stderr().is_terminal() => {
+                self.render_human(annotate_snippets::Renderer::styled(), source, origin)
+            }
+            ErrorFormat::Human => self.render_human(annotate_snippets::Renderer::plain(), source, origin),
+            ErrorFormat::Json => self.render_json(source, origin),
+        }
+    }
+
+    fn render_human(&self, renderer: annotate_snippets::Renderer, source: &str, origin: Option<&str>) -> String {
+        // annotate-snippets only borrows what it's given, so the suggestions are worded first.
+        let suggestions: Vec<String> = self.suggestions
+            .iter()
+            .map(|suggestion| format!("{}: `{}`", suggestion.message, suggestion.replacement))
+            .collect();
+        self.render_snippets(renderer, source, origin, &suggestions)
+    }
+
+    fn render_snippets(&self, renderer: annotate_snippets::Renderer, source: &str, origin: Option<&str>, suggestions: &[String]) -> String {
+        use annotate_snippets::{Level as AsLevel, Snippet};
+
+        let level = match self.level {
+            Level::Error => AsLevel::Error,
+            Level::Warning => AsLevel::Warning,
+        };
+
+        let mut message = level.title(&self.title);
+        if let Some(code) = &self.code {
+            message = message.id(code);
+        }
+        if !self.labels.is_empty() {
+            let mut snippet = Snippet::source(source).fold(true);
+            if let Some(origin) = origin {
+                snippet = snippet.origin(origin);
+            }
+            for label in &self.labels {
+                let label_level = if label.primary { level } else { AsLevel::Info };
+                snippet = snippet.annotation(label_level.span(label.span.clone()).label(&label.message));
+            }
+            message = message.snippet(snippet);
+        }
+        for (kind, footer) in &self.footers {
+            let footer_level = match kind {
+                FooterKind::Help => AsLevel::Help,
+                FooterKind::Note => AsLevel::Note,
+            };
+            message = message.footer(footer_level.title(footer));
+        }
+        for suggestion in suggestions {
+            message = message.footer(AsLevel::Help.title(suggestion));
+        }
+
+        renderer.render(message).to_string()
+    }
+
+    fn render_json(&self, source: &str, origin: Option<&str>) -> String {
+        // Spans into the command line don't point at a file tooling could open.
+        let spans = match origin {
+            Some(file) => self.labels
+                .iter()
+                .map(|label| json_span(source, file, &label.span, label.primary, Some(&label.message), None))
+                .collect(),
+            None => vec![],
+        };
+
+        let mut children: Vec<Json> = self.footers
+            .iter()
+            .map(|(kind, message)| json_child(kind.as_str(), message, vec![]))
+            .collect();
+        children.extend(self.suggestions.iter().map(|suggestion| {
+            let spans = match origin {
+                Some(file) => vec![json_span(source, file, &suggestion.span, true, None, Some(&suggestion.replacement))],
+                None => vec![],
+            };
+            json_child("help", &suggestion.message, spans)
+        }));
+
+        let rendered = self.render_human(annotate_snippets::Renderer::plain(), source, origin);
+        Json::Obj(vec![
+            ("$message_type", Json::Str("diagnostic".to_string())),
+            ("message", Json::Str(self.title.clone())),
+            ("code", self.code.as_deref().map_or(Json::Null, |code| Json::Obj(vec![
+                ("code", Json::Str(code.to_string())),
+                ("explanation", error_codes::explanation(code).map_or(Json::Null, |text| Json::Str(text.to_string()))),
+            ]))),
+            ("level", Json::Str(self.level.as_str().to_string())),
+            ("spans", Json::Arr(spans)),
+            ("children", Json::Arr(children)),
+            ("rendered", Json::Str(format!("{rendered}\n\n"))),
+        ]).to_string()
+    }
+}
+
+fn json_child(level: &str, message: &str, spans: Vec<Json>) -> Json {
+    Json::Obj(vec![
+        ("message", Json::Str(message.to_string())),
+        ("code", Json::Null),
+        ("level", Json::Str(level.to_string())),
+        ("spans", Json::Arr(spans)),
+        ("children", Json::Arr(vec![])),
+        ("rendered", Json::Null),
+    ])
+}
+
+/// A span as rustc writes it: byte offsets, plus 1-based lines and character columns and
+/// the source lines it covers.
+fn json_span(source: &str, file: &str, span: &Range<usize>, primary: bool, label: Option<&str>, replacement: Option<&str>) -> Json {
+    let (line_start, column_start) = line_column(source, span.start);
+    let (line_end, column_end) = line_column(source, span.end);
+
+    let text = source
+        .lines()
+        .enumerate()
+        .skip(line_start - 1)
+        .take(line_end - line_start + 1)
+        .map(|(i, line)| {
+            let line_no = i + 1;
+            let highlight_start = if line_no == line_start { column_start } else { 1 };
+            let highlight_end = if line_no == line_end { column_end } else { line.chars().count() + 1 };
+            Json::Obj(vec![
+                ("text", Json::Str(line.to_string())),
+                ("highlight_start", Json::Num(highlight_start)),
+                ("highlight_end", Json::Num(highlight_end)),
+            ])
+        })
+        .collect();
+
+    Json::Obj(vec![
+        ("file_name", Json::Str(file.to_string())),
+        ("byte_start", Json::Num(span.start)),
+        ("byte_end", Json::Num(span.end)),
+        ("line_start", Json::Num(line_start)),
+        ("line_end", Json::Num(line_end)),
+        ("column_start", Json::Num(column_start)),
+        ("column_end", Json::Num(column_end)),
+        ("is_primary", Json::Bool(primary)),
+        ("text", Json::Arr(text)),
+        ("label", label.map_or(Json::Null, |label| Json::Str(label.to_string()))),
+        ("suggested_replacement", replacement.map_or(Json::Null, |replacement| Json::Str(replacement.to_string()))),
+        ("suggestion_applicability", replacement.map_or(Json::Null, |_| Json::Str("MaybeIncorrect".to_string()))),
+        ("expansion", Json::Null),
+    ])
+}
+
+/// 1-based line and character column of byte `offset` in `source`.
+fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+/// Just enough JSON to write diagnostics with.
+enum Json {
+    Null,
+    Bool(bool),
+    Num(usize),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(&'static str, Json)>),
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn write_str(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+            f.write_char('"')?;
+            for c in s.chars() {
+                match c {
+                    '"' => f.write_str("\\\"")?,
+                    '\\' => f.write_str("\\\\")?,
+                    '\n' => f.write_str("\\n")?,
+                    '\r' => f.write_str("\\r")?,
+                    '\t' => f.write_str("\\t")?,
+                    c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                    c => f.write_char(c)?,
+                }
+            }
+            f.write_char('"')
+        }
+
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Num(value) => write!(f, "{value}"),
+            Self::Str(value) => write_str(f, value),
+            Self::Arr(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_char(']')
+            }
+            Self::Obj(fields) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+/// How many errors and warnings there have been, ours and rustc's.
+#[derive(Default)]
+struct Tally {
+    errors: usize,
+    warnings: usize,
+    /// Codes of the errors so far, to point at `--explain` at the end.
+    codes: BTreeSet<String>,
+}
+
+impl Tally {
+    fn count(&mut self, level: Level, code: Option<&str>) {
+        match level {
+            Level::Error => self.errors += 1,
+            Level::Warning => self.warnings += 1,
+        }
+        if let (Level::Error, Some(code)) = (level, code) {
+            self.codes.insert(code.to_string());
+        }
+    }
+}
+
+/// Collects diagnostics until they're flushed to stderr, or kept.
+///
+/// Emitting only needs `&self`, since diagnostics are found all over the compiler and most
+/// of it only has shared access to the [`CrustCompiler`](crate::compiler::CrustCompiler).
+#[derive(Default)]
+pub struct Diagnostics {
+    /// How flushed diagnostics are written to stderr, or `None` to keep them instead.
+    format: Option<ErrorFormat>,
+    pending: RefCell<Vec<Diagnostic>>,
+    /// Flushed diagnostics, when they're kept. rustc's emitter adds to these from its thread.
+    kept: Arc<Mutex<Vec<Diagnostic>>>,
+    /// rustc's emitter counts its diagnostics here too.
+    tally: Arc<Mutex<Tally>>,
+}
+
+impl Diagnostics {
+    pub fn new(format: ErrorFormat) -> Self {
+        Self { format: Some(format), ..Self::default() }
+    }
+
+    /// Diagnostics that are kept for [`Diagnostics::take`] rather than written out.
+    pub fn keeping() -> Self {
+        Self::default()
+    }
+
+    /// Everything kept so far, in the order it was reported.
+    pub fn take(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut *self.kept.lock().unwrap())
+    }
+
+    /// What rustc needs to report its diagnostics along with ours: kept with them if ours
+    /// are kept, and either way counted with them for [`Diagnostics::report_summary`]. Only
+    /// labels in the file `filename` are kept, since that's the source they index into.
+    pub fn for_rustc(&self, filename: FileName) -> ForRustc {
+        ForRustc { format: self.format, kept: self.kept.clone(), tally: self.tally.clone(), filename }
+    }
+
+    pub fn emit(&self, diagnostic: Diagnostic) {
+        self.tally.lock().unwrap().count(diagnostic.level, diagnostic.code.as_deref());
+        self.pending.borrow_mut().push(diagnostic);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.tally.lock().unwrap().errors
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.tally.lock().unwrap().warnings
+    }
+
+    /// Writes out (or keeps) everything emitted since the last flush.
+    pub fn flush(&self, source: &str, origin: Option<&str>) {
+        let mut pending = self.pending.borrow_mut();
+        match self.format {
+            Some(format) => pending.drain(..).for_each(|diagnostic| report(format, &diagnostic, source, origin)),
+            None => self.kept.lock().unwrap().extend(pending.drain(..)),
+        }
+    }
+
+    /// Writes the closing "aborting due to" line rustc would, for its errors and ours, and
+    /// where to read more about them, if there were any.
+    pub fn report_summary(&self) {
+        let errors = self.error_count();
+        let Some(format) = self.format.filter(|_| errors > 0) else {
+            return;
+        };
+
+        let title = if errors == 1 {
+            "aborting due to 1 previous error".to_string()
+        } else {
+            format!("aborting due to {errors} previous errors")
+        };
+        report(format, &Diagnostic::error(title), "", None);
+
+        let tally = self.tally.lock().unwrap();
+        let codes: Vec<&str> = tally.codes.iter().map(String::as_str).collect();
+        match codes.as_slice() {
+            [] => {}
+            [code] => {
+                report_failure_note(format, &format!("For more information about this error, try `crustc --explain {code}`."));
+            }
+            [first, ..] => {
+                report_failure_note(format, &format!("Some errors have detailed explanations: {}.", codes.join(", ")));
+                report_failure_note(format, &format!("For more information about an error, try `crustc --explain {first}`."));
+            }
+        }
+    }
+}
+
+pub struct ForRustc {
+    format: Option<ErrorFormat>,
+    kept: Arc<Mutex<Vec<Diagnostic>>>,
+    tally: Arc<Mutex<Tally>>,
+    filename: FileName,
+}
+
+impl ForRustc {
+    /// Swaps the session's emitter for one that keeps or writes what it's given, and leaves
+    /// summing up to us.
+    pub fn install(self, psess: &mut ParseSess) {
+        let source_map = psess.clone_source_map();
+        match self.format {
+            Some(format) => psess.dcx().set_emitter(Box::new(CountingEmitter {
+                inner: rustc_emitter(format, source_map),
+                tally: self.tally,
+            })),
+            None => psess.dcx().set_emitter(Box::new(KeepingEmitter {
+                kept: self.kept,
+                tally: self.tally,
+                source_map,
+                filename: self.filename,
+                bundle: rustc_errors::fallback_fluent_bundle(rustc_driver::DEFAULT_LOCALE_RESOURCES.to_vec(), false),
+            })),
+        }
+    }
+}
+
+/// The emitter rustc would have used for `format`.
+pub(crate) fn rustc_emitter(format: ErrorFormat, source_map: Arc<SourceMap>) -> Box<DynEmitter> {
+    let bundle = rustc_errors::fallback_fluent_bundle(rustc_driver::DEFAULT_LOCALE_RESOURCES.to_vec(), false);
+    match format {
+        ErrorFormat::Human => Box::new(
+            HumanEmitter::new(stderr_destination(ColorConfig::Auto), bundle).sm(Some(source_map)),
+        ),
+        ErrorFormat::Json => Box::new(JsonEmitter::new(
+            Box::new(BufWriter::new(io::stderr())),
+            Some(source_map),
+            bundle,
+            false,
+            HumanReadableErrorType::Default,
+            ColorConfig::Never,
+        )),
+    }
+}
+
+/// Whether `diag` is rustc summing up as it finishes: the "aborting due to" line, the count
+/// of warnings, or the notes on where to read more.
+fn is_rustc_summary(diag: &DiagInner, title: &str) -> bool {
+    diag.level() == rustc_errors::Level::FailureNote
+        || title.starts_with("aborting due to")
+        || title.ends_with("warning emitted")
+        || title.ends_with("warnings emitted")
+}
+
+/// The level of one of rustc's diagnostics, if it's one crustc counts.
+fn rustc_level(diag: &DiagInner) -> Option<Level> {
+    match diag.level() {
+        rustc_errors::Level::Warning | rustc_errors::Level::ForceWarning => Some(Level::Warning),
+        _ if diag.is_error() => Some(Level::Error),
+        _ => None,
+    }
+}
+
+/// Writes rustc's diagnostics as rustc would, but counts them instead of summing up.
+struct CountingEmitter {
+    inner: Box<DynEmitter>,
+    tally: Arc<Mutex<Tally>>,
+}
+
+impl Translate for CountingEmitter {
+    fn fluent_bundle(&self) -> Option<&FluentBundle> {
+        self.inner.fluent_bundle()
+    }
+
+    fn fallback_fluent_bundle(&self) -> &FluentBundle {
+        self.inner.fallback_fluent_bundle()
+    }
+}
+
+impl Emitter for CountingEmitter {
+    fn emit_diagnostic(&mut self, diag: DiagInner, registry: &Registry) {
+        let args = to_fluent_args(diag.args.iter());
+        let title = self.translate_messages(&diag.messages, &args).into_owned();
+        if is_rustc_summary(&diag, &title) {
+            return;
+        }
+        if let Some(level) = rustc_level(&diag) {
+            self.tally.lock().unwrap().count(level, diag.code.map(|code| code.to_string()).as_deref());
+        }
+        self.inner.emit_diagnostic(diag, registry);
+    }
+
+    fn emit_artifact_notification(&mut self, path: &std::path::Path, artifact_type: &str) {
+        self.inner.emit_artifact_notification(path, artifact_type);
+    }
+
+    fn emit_future_breakage_report(&mut self, diags: Vec<DiagInner>, registry: &Registry) {
+        self.inner.emit_future_breakage_report(diags, registry);
+    }
+
+    fn supports_color(&self) -> bool {
+        self.inner.supports_color()
+    }
+
+    fn source_map(&self) -> Option<&SourceMap> {
+        self.inner.source_map()
+    }
+}
+
+/// Turns rustc's diagnostics into [`Diagnostic`]s and keeps them.
+struct KeepingEmitter {
+    kept: Arc<Mutex<Vec<Diagnostic>>>,
+    tally: Arc<Mutex<Tally>>,
+    source_map: Arc<SourceMap>,
+    filename: FileName,
+    bundle: LazyFallbackBundle,
+}
+
+impl KeepingEmitter {
+    fn range(&self, span: rustc_span::Span) -> Option<Range<usize>> {
+        range(&self.source_map, &self.filename, span)
+    }
+
+    fn translate(&self, message: &DiagMessage, args: &FluentArgs<'_>) -> String {
+        self.translate_message(message, args).map(|text| text.into_owned()).unwrap_or_default()
+    }
+}
+
+impl Translate for KeepingEmitter {
+    fn fluent_bundle(&self) -> Option<&FluentBundle> {
+        None
+    }
+
+    fn fallback_fluent_bundle(&self) -> &FluentBundle {
+        &self.bundle
+    }
+}
+
+impl Emitter for KeepingEmitter {
+    fn emit_diagnostic(&mut self, diag: DiagInner, _registry: &Registry) {
+        let Some(level) = rustc_level(&diag) else {
+            return;
+        };
+        let args = to_fluent_args(diag.args.iter());
+
+        let title = self.translate_messages(&diag.messages, &args).into_owned();
+        // rustc sums up as it finishes, but whoever keeps diagnostics can count them.
+        if is_rustc_summary(&diag, &title) {
+            return;
+        }
+
+        let mut diagnostic = Diagnostic::new(level, title);
+        diagnostic.code = diag.code.map(|code| code.to_string());
+        self.tally.lock().unwrap().count(level, diagnostic.code.as_deref());
+        for label in diag.span.span_labels() {
+            if let Some(span) = self.range(label.span) {
+                let message = label.label.as_ref().map(|message| self.translate(message, &args)).unwrap_or_default();
+                diagnostic.labels.push(Label { span, message, primary: label.is_primary });
+            }
+        }
+        for child in &diag.children {
+            let kind = match child.level {
+                rustc_errors::Level::Help | rustc_errors::Level::OnceHelp => FooterKind::Help,
+                rustc_errors::Level::Note | rustc_errors::Level::OnceNote => FooterKind::Note,
+                _ => continue,
+            };
+            diagnostic.footers.push((kind, self.translate_messages(&child.messages, &args).into_owned()));
+        }
+        let suggestions: &[_] = match &diag.suggestions {
+            Suggestions::Enabled(suggestions) => suggestions,
+            Suggestions::Sealed(suggestions) => suggestions,
+            Suggestions::Disabled => &[],
+        };
+        for suggestion in suggestions {
+            // Only the simple ones: one replacement, in the source.
+            let Some([part]) = suggestion.substitutions.first().map(|substitution| substitution.parts.as_slice()) else {
+                continue;
+            };
+            if let Some(span) = self.range(part.span) {
+                diagnostic.suggestions.push(Suggestion {
+                    span,
+                    replacement: part.snippet.clone(),
+                    message: self.translate(&suggestion.msg, &args),
+                });
+            }
+        }
+        self.kept.lock().unwrap().push(diagnostic);
+    }
+
+    fn source_map(&self) -> Option<&SourceMap> {
+        Some(&self.source_map)
+    }
+}
+
+/// The notes after rustc's summary, which don't have a level of their own in human output.
+fn report_failure_note(format: ErrorFormat, message: &str) {
+    match format {
+        ErrorFormat::Human => eprintln!("{message}"),
+        ErrorFormat::Json => {
+            let json = Json::Obj(vec![
+                ("$message_type", Json::Str("diagnostic".to_string())),
+                ("message", Json::Str(message.to_string())),
+                ("code", Json::Null),
+                ("level", Json::Str("failure-note".to_string())),
+                ("spans", Json::Arr(vec![])),
+                ("children", Json::Arr(vec![])),
+                ("rendered", Json::Str(format!("{message}\n"))),
+            ]);
+            eprintln!("{json}");
+        }
+    }
+}
+
+/// Writes a single diagnostic to stderr.
+pub fn report(format: ErrorFormat, diagnostic: &Diagnostic, source: &str, origin: Option<&str>) {
+    let rendered = diagnostic.render(format, source, origin);
+    match format {
+        ErrorFormat::Human => eprintln!("{rendered}\n"),
+        ErrorFormat::Json => eprintln!("{rendered}"),
+    }
+}
+
+/// Byte range of `span` in the file `filename`, if that's where it is. rustc's positions
+/// count through every file it has loaded, so they're only offsets into one of them once
+/// its start is taken off.
+pub fn range(source_map: &SourceMap, filename: &FileName, span: rustc_span::Span) -> Option<Range<usize>> {
+    if span.is_dummy() {
+        return None;
+    }
+    let file = source_map.lookup_source_file(span.lo());
+    if file.name != *filename || span.hi() > file.end_position() {
+        return None;
+    }
+    Some((span.lo() - file.start_pos).0 as usize..(span.hi() - file.start_pos).0 as usize)
+}
@@ -0,0 +1,110 @@
+//! Running rustc itself, on the generated Rust or for anything else crustc hands off to it.
+
+use std::path::{Path, PathBuf};
+
+use rustc_driver::{Callbacks, run_compiler};
+use rustc_interface::interface;
+use rustc_session::config::Input;
+use rustc_span::{FileName, RealFileName};
+
+use crate::{cli, span_map::{Remap, SpanMap}};
+
+#[derive(Default)]
+struct BuildCallbacks {
+    /// Where to point errors in the generated file instead, when building one.
+    remap: Option<Remap>,
+    /// What to compile instead of the input named on the command line.
+    input: Option<Input>,
+}
+
+impl Callbacks for BuildCallbacks {
+    fn config(&mut self, config: &mut interface::Config) {
+        if let Some(remap) = self.remap.take() {
+            config.psess_created = Some(Box::new(move |psess| remap.install(psess)));
+        }
+        if let Some(input) = self.input.take() {
+            config.input = input;
+        }
+    }
+}
+
+/// Runs the rustc crustc is built on, returning its exit code.
+pub fn run_rustc(args: &[String]) -> i32 {
+    rustc_driver::catch_with_exit_code(|| run_compiler(args, &mut BuildCallbacks::default()))
+}
+
+/// Compiles generated Rust into everything in `options.emit` besides the Rust itself,
+/// returning rustc's exit code. It's read from `kept` if it was written there, and taken
+/// from memory otherwise. Errors are reported against `source` where `span_map` knows
+/// where they came from.
+pub fn build_outputs(generated: &str, kept: Option<&Path>, options: &cli::Options, source: &str, libc_rlib: &Path, span_map: SpanMap) -> i32 {
+    let emit: Vec<String> = options.emit
+        .iter()
+        .filter_map(|&kind| Some(format!("{}={}", kind.rustc_name()?, options.output_path(kind).display())))
+        .collect();
+    compile_generated(generated, kept, options, source, libc_rlib, span_map, format!("--emit={}", emit.join(",")))
+}
+
+/// Type-checks and lints generated Rust the way [`build_outputs`] would build it, without
+/// writing anything, returning rustc's exit code.
+pub fn check(generated: &str, options: &cli::Options, source: &str, libc_rlib: &Path, span_map: SpanMap) -> i32 {
+    compile_generated(generated, None, options, source, libc_rlib, span_map, "-Zno-codegen".to_string())
+}
+
+/// Runs rustc on generated Rust with the flags `options` ask for, plus `outputs` to say
+/// what it produces.
+fn compile_generated(generated: &str, kept: Option<&Path>, options: &cli::Options, source: &str, libc_rlib: &Path, span_map: SpanMap, outputs: String) -> i32 {
+    let crate_name = options.crate_name();
+    // Rust that only exists in memory is named like the `rs` output in errors, so they read
+    // the same either way.
+    let generated_filepath = kept.map_or_else(|| PathBuf::from(format!("{}.generated.rs", options.source_name())), Path::to_path_buf);
+
+    let mut args = vec![
+        "ignored".to_string(),
+        "--edition=2021".to_string(),
+        "-Cpanic=abort".to_string(),
+        format!("--crate-type={}", options.crate_type.rustc_name()),
+        format!("--crate-name={crate_name}"),
+        format!("--extern=libc={}", libc_rlib.display()),
+        "-lc".to_string(),
+        outputs,
+        format!("--error-format={}", options.error_format.as_str()),
+    ];
+    if let Some(target) = &options.target {
+        args.push(format!("--target={target}"));
+    }
+    if options.optimize {
+        args.push("-O".to_string());
+    }
+    if options.debuginfo {
+        // The generated file is laid out along the source, so debug info can name that instead.
+        // The extra `./` keeps rustc from taking the two for the same file once the source is
+        // loaded to report errors against.
+        let input = Path::new(options.source_name());
+        let source = input.parent().unwrap_or(Path::new("")).join(".").join(input.file_name().unwrap_or_default());
+        args.push("-g".to_string());
+        args.push(format!("--remap-path-prefix={}={}", generated_filepath.display(), source.display()));
+        args.push("-Zremap-path-scope=debuginfo".to_string());
+    }
+    args.extend(options.codegen.iter().map(|opt| format!("-C{opt}")));
+    args.extend(options.link_paths.iter().map(|path| format!("-L{path}")));
+    args.extend(options.link_libs.iter().map(|lib| format!("-l{lib}")));
+    args.extend(options.externs.iter().map(|(name, rlib)| format!("--extern={name}={}", rlib.display())));
+    args.push(generated_filepath.display().to_string());
+
+    let mut callbacks = BuildCallbacks {
+        remap: Some(Remap {
+            map: span_map,
+            generated: generated_filepath.to_path_buf(),
+            source: PathBuf::from(options.source_name()),
+            source_text: source.to_string(),
+            format: options.error_format,
+        }),
+        input: kept.is_none().then(|| Input::Str {
+            name: FileName::Real(RealFileName::LocalPath(generated_filepath.clone())),
+            input: generated.to_string(),
+        }),
+    };
+    rustc_driver::catch_with_exit_code(|| run_compiler(&args, &mut callbacks))
+}
+
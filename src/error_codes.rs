@@ -0,0 +1,34 @@
+//! Codes for the errors only Crust has, in the spirit of `rustc_error_codes`.
+//!
+//! Each code has a long-form explanation in `error_codes/CXXXX.md`, shown by
+//! `crustc --explain CXXXX`. Codes are never reused: if an error stops being emitted,
+//! say so in its explanation rather than removing it.
+
+macro_rules! error_codes {
+    ($($code:ident,)*) => {
+        $(pub const $code: &str = stringify!($code);)*
+
+        /// Every Crust error code, with its explanation.
+        pub static REGISTRY: &[(&str, &str)] = &[
+            $((stringify!($code), include_str!(concat!("error_codes/", stringify!($code), ".md"))),)*
+        ];
+    };
+}
+
+error_codes! {
+    C0001,
+    C0002,
+    C0003,
+    C0004,
+    C0005,
+    C0006,
+    C0007,
+    C0008,
+    C0009,
+}
+
+/// The explanation for `code`, accepting `C0001` or `c0001`.
+pub fn explanation(code: &str) -> Option<&'static str> {
+    let code = code.to_ascii_uppercase();
+    REGISTRY.iter().find(|(known, _)| *known == code).map(|(_, explanation)| *explanation)
+}
@@ -0,0 +1,263 @@
+//! A transpiler from Crust to Rust.
+//!
+//! [`transpile`] turns the source of a `.crs` file into Rust without touching the filesystem
+//! or spawning anything, for build tools and tests. The `crustc` binary is a command line
+//! around it that goes on to compile what it generates.
+//!
+//! crustc is built on rustc, so anything using it needs `#![feature(rustc_private)]` and
+//! `extern crate rustc_driver;` to link it.
+
+#![feature(rustc_private)]
+
+extern crate rustc_abi;
+extern crate rustc_ast;
+extern crate rustc_ast_pretty;
+extern crate rustc_data_structures;
+extern crate rustc_driver;
+extern crate rustc_error_codes;
+extern crate rustc_errors;
+extern crate rustc_hash;
+extern crate rustc_hir;
+extern crate rustc_interface;
+extern crate rustc_lexer;
+extern crate rustc_middle;
+extern crate rustc_parse;
+extern crate rustc_session;
+extern crate rustc_span;
+
+mod c_header;
+pub mod cli;
+mod comments;
+mod compiler;
+pub mod diagnostics;
+pub mod driver;
+pub mod error_codes;
+pub mod libc_rlib;
+mod pretty;
+mod printf;
+pub mod project;
+pub mod session;
+pub mod span_map;
+
+use std::{path::PathBuf, str::FromStr};
+
+use quote::ToTokens;
+
+use compiler::CrustCompiler;
+use diagnostics::{Diagnostic, Diagnostics, ErrorFormat};
+use span_map::{EmittedToken, SpanMap};
+
+/// How the generated crate handles panics. Crust output is `#![no_std]`, so we
+/// always have to provide the `#[panic_handler]` ourselves.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PanicHandler {
+    /// Call `abort()` without printing anything.
+    Abort,
+    /// Print the panic location and message to stderr, then `abort()`.
+    Print,
+    /// Forward to a user function marked with `#[crust::panic_handler]`.
+    User,
+}
+
+impl FromStr for PanicHandler {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "print" => Ok(Self::Print),
+            "user" => Ok(Self::User),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What kind of crate the generated Rust is.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CrateType {
+    /// A program, with the entry point and panic handler a `#![no_std]` binary needs.
+    Bin,
+    /// A Rust library for other Crust crates to use, leaving the entry point and panic
+    /// handler to the program it ends up in.
+    Lib,
+    /// A static library for C programs to link, with its own panic handler.
+    Staticlib,
+    /// A shared library for C programs to load, with its own panic handler.
+    Cdylib,
+}
+
+impl CrateType {
+    /// The name rustc gives this in `--crate-type`.
+    pub fn rustc_name(self) -> &'static str {
+        match self {
+            Self::Bin => "bin",
+            Self::Lib => "lib",
+            Self::Staticlib => "staticlib",
+            Self::Cdylib => "cdylib",
+        }
+    }
+
+    /// The file rustc would build the crate `crate_name` into, on Linux.
+    pub fn file_name(self, crate_name: &str) -> String {
+        match self {
+            Self::Bin => crate_name.to_string(),
+            Self::Lib => format!("lib{crate_name}.rlib"),
+            Self::Staticlib => format!("lib{crate_name}.a"),
+            Self::Cdylib => format!("lib{crate_name}.so"),
+        }
+    }
+
+    /// Whether nothing else gets linked in after this, so it has to bring its own panic
+    /// handler.
+    pub fn is_final(self) -> bool {
+        self != Self::Lib
+    }
+}
+
+impl FromStr for CrateType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bin" => Ok(Self::Bin),
+            "lib" | "rlib" => Ok(Self::Lib),
+            "staticlib" => Ok(Self::Staticlib),
+            "cdylib" => Ok(Self::Cdylib),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What [`transpile`] does, with the same defaults as `crustc`.
+pub struct TranspileOptions {
+    /// The name diagnostics give the source. Nothing is read from it.
+    pub filename: String,
+    pub panic_handler: PanicHandler,
+    pub crate_type: CrateType,
+    /// Crates the source uses besides libc, by name and rlib.
+    pub externs: Vec<(String, PathBuf)>,
+    /// Library search paths, as `-L` takes them, where rustc finds what the externs use.
+    pub link_paths: Vec<String>,
+    /// The libc rlib to check the source against. If `None`, it's found the way `crustc`
+    /// finds it, but never built: without a cached build that's an error.
+    pub libc_path: Option<PathBuf>,
+    /// The target the libc rlib is found for, when it isn't given.
+    pub target: Option<String>,
+    /// Pretty-print the generated Rust.
+    pub fmt: bool,
+    /// Also run rustfmt over the generated Rust, if it's installed.
+    pub rustfmt: bool,
+    /// Also declare what the crate exports to C, in [`TranspileOutput::c_header`].
+    pub c_header: bool,
+    /// Write diagnostics to stderr as `crustc` does, rather than returning them.
+    pub error_format: Option<ErrorFormat>,
+}
+
+impl Default for TranspileOptions {
+    fn default() -> Self {
+        Self {
+            filename: "main.crs".to_string(),
+            panic_handler: PanicHandler::Print,
+            crate_type: CrateType::Bin,
+            externs: vec![],
+            link_paths: vec![],
+            libc_path: None,
+            target: None,
+            fmt: true,
+            rustfmt: false,
+            c_header: false,
+            error_format: None,
+        }
+    }
+}
+
+pub struct TranspileOutput {
+    /// The generated Rust.
+    pub rust: String,
+    /// Warnings about the source, unless they were written to stderr.
+    pub warnings: Vec<Diagnostic>,
+    /// The C header, if it was asked for.
+    pub c_header: Option<String>,
+    emitted_tokens: Vec<EmittedToken>,
+}
+
+impl TranspileOutput {
+    /// Where the tokens of the generated Rust came from in the source.
+    pub fn span_map(&self) -> SpanMap {
+        SpanMap::new(&self.emitted_tokens, &self.rust)
+    }
+
+    /// The generated Rust laid out along the lines of `source`, so that debug info for it
+    /// steps through the source, and its span map.
+    pub fn source_layout(&self, source: &str) -> (String, SpanMap) {
+        let layout = self.span_map().source_layout(&self.rust, source);
+        let map = SpanMap::new(&self.emitted_tokens, &layout);
+        (layout, map)
+    }
+}
+
+/// Transpiles the Crust program `source` to Rust.
+///
+/// On failure this returns the errors and any warnings, with spans into `source`, which
+/// [`Diagnostic::render`] can show. With [`TranspileOptions::error_format`] set they've been
+/// written to stderr already, and nothing is returned.
+pub fn transpile(source: &str, options: &TranspileOptions) -> Result<TranspileOutput, Vec<Diagnostic>> {
+    let diagnostics = match options.error_format {
+        Some(format) => Diagnostics::new(format),
+        None => Diagnostics::keeping(),
+    };
+    let error_format = options.error_format.unwrap_or_default();
+
+    let libc_rlib = match libc_rlib::locate_built(options.libc_path.as_deref(), options.target.as_deref()) {
+        Ok(rlib) => rlib,
+        Err(err) => {
+            diagnostics.emit(err.diagnostic());
+            diagnostics.flush("", None);
+            return Err(diagnostics.take());
+        }
+    };
+
+    let mut compiler = CrustCompiler::new(
+        source.to_string(),
+        options.filename.clone(),
+        options.panic_handler,
+        options.crate_type,
+        diagnostics,
+    );
+    compiler.wants_c_header = options.c_header;
+    let mut args = vec![
+        "ignored".to_string(),
+        "--edition=2021".to_string(),
+        "-Cpanic=abort".to_string(),
+        format!("--error-format={}", error_format.as_str()),
+        format!("--crate-type={}", options.crate_type.rustc_name()),
+        format!("--extern=libc={}", libc_rlib.display()),
+    ];
+    args.extend(options.externs.iter().map(|(name, rlib)| format!("--extern={name}={}", rlib.display())));
+    args.extend(options.link_paths.iter().map(|path| format!("-L{path}")));
+    // rustc insists on an input, but compiles the source it's given in the callbacks.
+    args.push(options.filename.clone());
+    let result = rustc_driver::catch_fatal_errors(|| rustc_driver::run_compiler(&args, &mut compiler));
+
+    // rustc's errors were counted with ours, so one closing line covers both.
+    if result.is_err() || compiler.diagnostics.has_errors() {
+        compiler.diagnostics.report_summary();
+        return Err(compiler.diagnostics.take());
+    }
+
+    let mut rust = compiler.outfile.into_token_stream().to_string();
+    if options.fmt {
+        rust = pretty::print(&rust).unwrap_or(rust);
+    }
+    if options.rustfmt {
+        rust = pretty::rustfmt(rust);
+    }
+    rust = comments::insert(&SpanMap::new(&compiler.emitted_tokens, &rust), &rust, source);
+
+    Ok(TranspileOutput {
+        rust,
+        warnings: compiler.diagnostics.take(),
+        c_header: compiler.c_header,
+        emitted_tokens: compiler.emitted_tokens,
+    })
+}
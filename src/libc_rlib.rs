@@ -0,0 +1,144 @@
+//! Finding the rlib for our `libc` support crate.
+//!
+//! Generated code links against `libs/libc`, and the rlib has to come from the exact rustc
+//! crustc is built on. Rather than rely on whatever cargo left in `target/`, crustc carries
+//! the crate's source and builds it into a cache directory the first time it's needed.
+
+use std::{
+    env, fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::diagnostics::{Diagnostic, ErrorFormat};
+
+const LIBC_SOURCE: &str = include_str!("../libs/libc/libc.rs");
+
+/// Overrides the libc rlib, same as `--libc-path`.
+pub const LIBC_ENV: &str = "CRUST_LIBC";
+
+pub enum LibcError {
+    /// `--libc-path` or `CRUST_LIBC` points at something that doesn't exist.
+    MissingOverride(PathBuf),
+    /// There's no cached build, and we were asked not to make one.
+    NotBuilt(PathBuf),
+    CreateCacheDir(PathBuf, std::io::Error),
+    WriteSource(PathBuf, std::io::Error),
+    /// rustc failed to build the crate, with this exit code. It has already reported why.
+    Build(i32),
+}
+
+impl std::fmt::Display for LibcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingOverride(path) => write!(f, "libc rlib `{}` does not exist", path.display()),
+            Self::NotBuilt(path) => write!(f, "libc rlib `{}` hasn't been built yet", path.display()),
+            Self::CreateCacheDir(path, err) => write!(f, "couldn't create cache directory `{}`: {err}", path.display()),
+            Self::WriteSource(path, err) => write!(f, "couldn't write `{}`: {err}", path.display()),
+            Self::Build(code) => write!(f, "building the libc crate failed with exit code {code}"),
+        }
+    }
+}
+
+impl LibcError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error("couldn't find the libc crate").note(self.to_string());
+        if let Self::NotBuilt(_) = self {
+            return diagnostic.help("only a build fills the cache, so build something with crustc once first, or use `--libc-path`");
+        }
+        diagnostic.help("point `--libc-path` or `CRUST_LIBC` at a libc rlib built by this version of rustc")
+    }
+}
+
+/// Returns the libc rlib to link against: `libc_path` if given, then `CRUST_LIBC`,
+/// and otherwise a cached build of the bundled source for `target` (the host if `None`).
+pub fn locate(libc_path: Option<&Path>, target: Option<&str>, error_format: ErrorFormat) -> Result<PathBuf, LibcError> {
+    match locate_built(libc_path, target) {
+        Err(LibcError::NotBuilt(rlib)) => build(rlib.parent().expect("the cached rlib is in a directory"), target, error_format),
+        located => located,
+    }
+}
+
+/// Builds the bundled source for `target` into `dir/liblibc.rlib`, returning its path.
+pub fn build(dir: &Path, target: Option<&str>, error_format: ErrorFormat) -> Result<PathBuf, LibcError> {
+    let rlib = dir.join("liblibc.rlib");
+    fs::create_dir_all(dir).map_err(|err| LibcError::CreateCacheDir(dir.to_path_buf(), err))?;
+
+    // Everything is written under a name of its own and renamed into place, so a build
+    // running alongside us, in this process or another, never sees half a file.
+    let scratch = scratch_suffix();
+    let source = dir.join("libc.rs");
+    let partial_source = dir.join(format!("libc.{scratch}.rs"));
+    fs::write(&partial_source, LIBC_SOURCE).map_err(|err| LibcError::WriteSource(partial_source.clone(), err))?;
+    fs::rename(&partial_source, &source).map_err(|err| LibcError::WriteSource(source.clone(), err))?;
+
+    let partial = dir.join(format!("liblibc.{scratch}.rlib"));
+    let mut args = vec![
+        "ignored".to_string(),
+        "--edition=2021".to_string(),
+        "-Cpanic=abort".to_string(),
+        "--crate-type=rlib".to_string(),
+        "--crate-name=libc".to_string(),
+        "--cap-lints=allow".to_string(),
+        format!("--error-format={}", error_format.as_str()),
+        "-o".to_string(),
+        partial.display().to_string(),
+        source.display().to_string(),
+    ];
+    if let Some(target) = target {
+        args.push(format!("--target={target}"));
+    }
+    let status = crate::driver::run_rustc(&args);
+    if status != rustc_driver::EXIT_SUCCESS {
+        let _ = fs::remove_file(&partial);
+        return Err(LibcError::Build(status));
+    }
+
+    fs::rename(&partial, &rlib).map_err(|err| LibcError::WriteSource(rlib.clone(), err))?;
+    Ok(rlib)
+}
+
+/// Like [`locate`], but without building the bundled source if there's no cached build.
+pub fn locate_built(libc_path: Option<&Path>, target: Option<&str>) -> Result<PathBuf, LibcError> {
+    let overridden = libc_path.map(Path::to_path_buf).or_else(|| env::var_os(LIBC_ENV).map(PathBuf::from));
+    if let Some(path) = overridden {
+        return if path.exists() { Ok(path) } else { Err(LibcError::MissingOverride(path)) };
+    }
+
+    let rlib = cache_dir().join(cache_key(target)).join("liblibc.rlib");
+    if rlib.exists() { Ok(rlib) } else { Err(LibcError::NotBuilt(rlib)) }
+}
+
+/// Tells apart the files of builds running at the same time, even within one process.
+fn scratch_suffix() -> String {
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    format!("{}-{}", std::process::id(), BUILDS.fetch_add(1, Ordering::Relaxed))
+}
+
+fn cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        return PathBuf::from(dir).join("crustc");
+    }
+    if let Some(home) = env::var_os("HOME") {
+        return PathBuf::from(home).join(".cache").join("crustc");
+    }
+    env::temp_dir().join("crustc")
+}
+
+/// Identifies a build of the libc crate: rlibs are only usable by the rustc that built
+/// them, for the target they were built for, and the bundled source changes with crustc.
+fn cache_key(target: Option<&str>) -> String {
+    let version = rustc_interface::util::rustc_version_str().unwrap_or("unknown");
+
+    let mut hasher = DefaultHasher::new();
+    version.hash(&mut hasher);
+    LIBC_SOURCE.hash(&mut hasher);
+    target.hash(&mut hasher);
+
+    let version: String = version
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    format!("libc-{version}-{:016x}", hasher.finish())
+}
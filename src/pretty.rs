@@ -0,0 +1,53 @@
+//! Printing the generated Rust readably without depending on rustfmt.
+//!
+//! `syn` only prints a flat token dump, so the dump is parsed back with rustc's parser and
+//! printed with `rustc_ast_pretty`, the printer behind `-Zunpretty`. That's the same on every
+//! machine, and rustfmt can still be run over the result with `--rustfmt`.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use rustc_ast_pretty::pprust;
+use rustc_session::parse::ParseSess;
+use rustc_span::{edition::Edition, FileName};
+
+/// Pretty-prints `tokens`, or returns `None` if rustc can't parse them.
+pub fn print(tokens: &str) -> Option<String> {
+    rustc_span::create_session_globals_then(Edition::Edition2021, &[], None, || {
+        // Anything that fails to parse is reported when it's compiled, so stay quiet here.
+        let psess = ParseSess::with_silent_emitter(rustc_driver::DEFAULT_LOCALE_RESOURCES.to_vec(), String::new(), false);
+        let mut parser = rustc_parse::new_parser_from_source_str(
+            &psess,
+            FileName::Custom("generated".to_string()),
+            tokens.to_string(),
+        ).map_err(|errs| errs.into_iter().for_each(|err| err.cancel())).ok()?;
+        let krate = parser.parse_crate_mod().map_err(|err| err.cancel()).ok()?;
+        Some(pprust::crate_to_string_for_macros(&krate))
+    })
+}
+
+/// Runs `source` through rustfmt, or returns it as is if rustfmt isn't around or fails.
+pub fn rustfmt(source: String) -> String {
+    // Reordering would stop the output lining up with the tokens crustc emitted.
+    let child = Command::new("rustfmt")
+        .arg("--edition=2021")
+        .arg("--config=reorder_imports=false,reorder_modules=false")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(mut child) = child else {
+        return source;
+    };
+
+    // rustfmt only starts writing once it has all of stdin, so this can't deadlock.
+    let written = child.stdin.take().unwrap().write_all(source.as_bytes());
+    match child.wait_with_output() {
+        Ok(output) if written.is_ok() && output.status.success() => {
+            String::from_utf8(output.stdout).unwrap_or(source)
+        }
+        _ => source,
+    }
+}
@@ -0,0 +1,422 @@
+//! Everything crustc knows about printf: how Rust types are passed through C varargs,
+//! and how Rust-style `{}` format strings translate to printf conversions.
+
+use rustc_middle::ty::{self, Ty, TyCtxt};
+
+/// What an argument looks like to printf once it has gone through C's default
+/// argument promotions.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PrintfClass {
+    Int,
+    UInt,
+    LongLong,
+    ULongLong,
+    PtrDiff,
+    Size,
+    Double,
+    Char,
+    /// Printed as `true`/`false` through `%s`.
+    Bool,
+    /// A `*const c_char` (or a string literal, which crustc lowers to one).
+    CString,
+    /// A `*const CStr`, printed through `%s` after `(*ptr).as_ptr()`.
+    CStr,
+    Pointer,
+}
+
+/// A classified printf argument.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PrintfArg {
+    pub class: PrintfClass,
+    /// Whether the value has to be cast before it can be passed through varargs
+    /// (`f32` to `f64`, integers smaller than `int` to `int`).
+    pub promote: bool,
+}
+
+impl PrintfClass {
+    /// The length modifier printf needs to read this class.
+    pub fn length(self) -> &'static str {
+        match self {
+            Self::LongLong | Self::ULongLong => "ll",
+            Self::PtrDiff => "t",
+            Self::Size => "z",
+            _ => "",
+        }
+    }
+
+    pub fn default_conversion(self) -> char {
+        match self {
+            Self::Int | Self::LongLong | Self::PtrDiff => 'd',
+            Self::UInt | Self::ULongLong | Self::Size => 'u',
+            Self::Double => 'g',
+            Self::Char => 'c',
+            Self::Bool | Self::CString | Self::CStr => 's',
+            Self::Pointer => 'p',
+        }
+    }
+
+    pub fn is_integer(self) -> bool {
+        matches!(self, Self::Int | Self::UInt | Self::LongLong | Self::ULongLong | Self::PtrDiff | Self::Size)
+    }
+
+    /// The C type to cast a promoted argument to.
+    pub fn promoted_type(self) -> &'static str {
+        match self {
+            Self::UInt => "c_uint",
+            Self::Double => "c_double",
+            _ => "c_int",
+        }
+    }
+}
+
+/// Classifies `ty` as a printf argument, or `None` if it can't be passed to printf at all.
+/// `is_str_literal` marks string literals, which crustc turns into C strings.
+pub fn classify<'tcx>(tcx: TyCtxt<'tcx>, ty: Ty<'tcx>, is_str_literal: bool) -> Option<PrintfArg> {
+    use rustc_middle::ty::{FloatTy, IntTy, UintTy};
+
+    let arg = |class, promote| Some(PrintfArg { class, promote });
+    match ty.kind() {
+        ty::Int(IntTy::I8 | IntTy::I16) => arg(PrintfClass::Int, true),
+        ty::Int(IntTy::I32) => arg(PrintfClass::Int, false),
+        ty::Int(IntTy::I64) => arg(PrintfClass::LongLong, false),
+        ty::Int(IntTy::Isize) => arg(PrintfClass::PtrDiff, false),
+        ty::Uint(UintTy::U8 | UintTy::U16) => arg(PrintfClass::UInt, true),
+        ty::Uint(UintTy::U32) => arg(PrintfClass::UInt, false),
+        ty::Uint(UintTy::U64) => arg(PrintfClass::ULongLong, false),
+        ty::Uint(UintTy::Usize) => arg(PrintfClass::Size, false),
+        ty::Float(FloatTy::F32) => arg(PrintfClass::Double, true),
+        ty::Float(FloatTy::F64) => arg(PrintfClass::Double, false),
+        ty::Char => arg(PrintfClass::Char, true),
+        ty::Bool => arg(PrintfClass::Bool, false),
+        ty::RawPtr(pointee, _) => match pointee.kind() {
+            ty::Int(IntTy::I8) | ty::Uint(UintTy::U8) => arg(PrintfClass::CString, false),
+            ty::Adt(def, _) if tcx.crate_name(def.did().krate).as_str() == "core" && tcx.item_name(def.did()).as_str() == "CStr" => arg(PrintfClass::CStr, false),
+            _ => arg(PrintfClass::Pointer, false),
+        },
+        ty::Ref(_, pointee, _) if is_str_literal && pointee.is_str() => arg(PrintfClass::CString, false),
+        _ => None,
+    }
+}
+
+/// A piece of a Rust-style format string.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Piece {
+    Lit(String),
+    Placeholder(FormatSpec),
+}
+
+/// The parts of a `{:...}` placeholder that have a printf equivalent.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct FormatSpec {
+    pub zero_pad: bool,
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+    pub kind: Option<char>,
+}
+
+/// Splits a Rust-style format string into literal text and placeholders.
+/// Only positional `{}` placeholders are supported, since every argument has to line up
+/// with exactly one printf conversion.
+pub fn parse_rust_format(fmt: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = vec![];
+    let mut lit = String::new();
+    let mut chars = fmt.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                lit.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                lit.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err("unterminated `{` in format string".to_string()),
+                    }
+                }
+
+                if !lit.is_empty() {
+                    pieces.push(Piece::Lit(std::mem::take(&mut lit)));
+                }
+                pieces.push(Piece::Placeholder(parse_format_spec(&placeholder)?));
+            }
+            '}' => return Err("unmatched `}` in format string".to_string()),
+            c => lit.push(c),
+        }
+    }
+
+    if !lit.is_empty() {
+        pieces.push(Piece::Lit(lit));
+    }
+
+    Ok(pieces)
+}
+
+fn parse_format_spec(placeholder: &str) -> Result<FormatSpec, String> {
+    let spec = match placeholder.split_once(':') {
+        Some(("", spec)) => spec,
+        None if placeholder.is_empty() => return Ok(FormatSpec::default()),
+        _ => return Err(format!("`{{{placeholder}}}`: only positional `{{}}` placeholders are supported")),
+    };
+
+    let mut rest = spec;
+    let mut result = FormatSpec::default();
+
+    if let Some(stripped) = rest.strip_prefix('0') {
+        result.zero_pad = true;
+        rest = stripped;
+    }
+
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+
+    let n = digits(rest);
+    if n > 0 {
+        result.width = Some(rest[..n].parse().map_err(|_| format!("`{{{placeholder}}}`: width is too large"))?);
+        rest = &rest[n..];
+    }
+
+    if let Some(stripped) = rest.strip_prefix('.') {
+        let n = digits(stripped);
+        if n == 0 {
+            return Err(format!("`{{{placeholder}}}`: expected a precision after `.`"));
+        }
+        result.precision = Some(stripped[..n].parse().map_err(|_| format!("`{{{placeholder}}}`: precision is too large"))?);
+        rest = &stripped[n..];
+    }
+
+    let mut kind = rest.chars();
+    result.kind = kind.next();
+    if kind.next().is_some() {
+        return Err(format!("`{{{placeholder}}}`: unsupported format spec"));
+    }
+
+    Ok(result)
+}
+
+/// Builds the printf conversion for a placeholder whose argument is of class `class`.
+pub fn printf_conversion(spec: &FormatSpec, class: PrintfClass) -> Result<String, String> {
+    let conversion = match spec.kind {
+        // Rust's `{:.N}` means N digits after the point, which is `%f` rather than `%g`.
+        None | Some('?') if class == PrintfClass::Double && spec.precision.is_some() => 'f',
+        None | Some('?') => class.default_conversion(),
+        Some(c @ ('x' | 'X' | 'o')) if class.is_integer() => c,
+        Some(c @ ('e' | 'E')) if class == PrintfClass::Double => c,
+        Some('p') if matches!(class, PrintfClass::Pointer | PrintfClass::CString | PrintfClass::CStr) => 'p',
+        Some(c) => return Err(format!("`{c}` can't be used to format this argument")),
+    };
+
+    let mut out = String::from("%");
+    if spec.zero_pad {
+        out.push('0');
+    }
+    if let Some(width) = spec.width {
+        out.push_str(&width.to_string());
+    }
+    if let Some(precision) = spec.precision {
+        out.push('.');
+        out.push_str(&precision.to_string());
+    }
+    out.push_str(class.length());
+    out.push(conversion);
+    Ok(out)
+}
+
+/// Escapes literal text so printf prints it unchanged.
+pub fn escape_printf(lit: &str) -> String {
+    lit.replace('%', "%%")
+}
+
+/// The sizes of C's integer types on the target.
+#[derive(Clone, Copy, Debug)]
+pub struct CTarget {
+    pub int_bits: u64,
+    pub long_bits: u64,
+    pub pointer_bits: u64,
+}
+
+impl CTarget {
+    pub fn new(tcx: TyCtxt<'_>) -> Self {
+        let target = &tcx.sess.target;
+        let pointer_bits = u64::from(target.pointer_width);
+        Self {
+            int_bits: target.c_int_width.parse().unwrap_or(32),
+            long_bits: if target.is_like_windows { 32 } else { pointer_bits },
+            pointer_bits,
+        }
+    }
+
+    /// How wide an argument of class `class` is once it's been passed through varargs.
+    pub fn bits(self, class: PrintfClass) -> u64 {
+        match class {
+            PrintfClass::LongLong | PrintfClass::ULongLong => 64,
+            PrintfClass::PtrDiff | PrintfClass::Size => self.pointer_bits,
+            _ => self.int_bits,
+        }
+    }
+}
+
+/// What a printf conversion expects to read from its argument.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Expected {
+    /// An integer of this many bits. Signedness isn't checked, since printing an unsigned
+    /// value with `%d` (or a signed one with `%x`) is common and well defined in practice.
+    Int { bits: u64, c_type: &'static str },
+    Double,
+    CString,
+    Pointer,
+}
+
+impl Expected {
+    pub fn describe(self) -> String {
+        match self {
+            Self::Int { c_type, .. } => format!("`{c_type}`"),
+            Self::Double => "`c_double`".to_string(),
+            Self::CString => "a C string".to_string(),
+            Self::Pointer => "a pointer".to_string(),
+        }
+    }
+}
+
+/// A conversion in a printf format string, like `%-5.2f`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Conversion {
+    /// Where the conversion is in the format string, `%` included.
+    pub range: std::ops::Range<usize>,
+    /// How many `*` widths and precisions it has, each of which reads an extra `c_int` argument.
+    pub stars: usize,
+    pub length: String,
+    pub conversion: char,
+}
+
+impl Conversion {
+    /// What this conversion expects its (final) argument to be.
+    pub fn expected(&self, target: CTarget) -> Result<Expected, String> {
+        let int = |bits, c_type| Ok(Expected::Int { bits, c_type });
+        let spec = &self.length;
+        match self.conversion {
+            'd' | 'i' | 'u' | 'o' | 'x' | 'X' => match spec.as_str() {
+                "" | "hh" | "h" => int(target.int_bits, "c_int"),
+                "l" => int(target.long_bits, "c_long"),
+                "ll" | "j" => int(64, "c_longlong"),
+                "z" => int(target.pointer_bits, "usize"),
+                "t" => int(target.pointer_bits, "isize"),
+                _ => Err(format!("`{spec}` can't be used with `%{}`", self.conversion)),
+            },
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' | 'a' | 'A' => match spec.as_str() {
+                "" | "l" => Ok(Expected::Double),
+                "L" => Err("`long double` isn't supported by crust".to_string()),
+                _ => Err(format!("`{spec}` can't be used with `%{}`", self.conversion)),
+            },
+            'c' if spec.is_empty() || spec == "l" => int(target.int_bits, "c_int"),
+            's' if spec.is_empty() => Ok(Expected::CString),
+            'p' | 'n' if spec.is_empty() => Ok(Expected::Pointer),
+            c if "diuoxXfFeEgGaAcspn".contains(c) => Err(format!("`{spec}` can't be used with `%{c}`")),
+            c => Err(format!("unknown conversion `%{c}`")),
+        }
+    }
+}
+
+/// Finds every conversion in a printf format string. Offsets are into `fmt` as written,
+/// escapes and all, so they can be turned back into spans.
+pub fn parse_printf_format(fmt: &str) -> Result<Vec<Conversion>, (std::ops::Range<usize>, String)> {
+    let bytes = fmt.as_bytes();
+    let mut conversions = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        i += 1;
+        if bytes.get(i) == Some(&b'%') {
+            i += 1;
+            continue;
+        }
+
+        let mut stars = 0;
+        while i < bytes.len() && b"-+ #0".contains(&bytes[i]) {
+            i += 1;
+        }
+        if bytes.get(i) == Some(&b'*') {
+            stars += 1;
+            i += 1;
+        }
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        if bytes.get(i) == Some(&b'.') {
+            i += 1;
+            if bytes.get(i) == Some(&b'*') {
+                stars += 1;
+                i += 1;
+            }
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+
+        let length_start = i;
+        while i < bytes.len() && b"hljztL".contains(&bytes[i]) {
+            i += 1;
+        }
+        let length = fmt[length_start..i].to_string();
+
+        let Some(conversion) = fmt[i..].chars().next() else {
+            return Err((start..i, "incomplete conversion at the end of the format string".to_string()));
+        };
+        i += conversion.len_utf8();
+
+        conversions.push(Conversion { range: start..i, stars, length, conversion });
+    }
+
+    Ok(conversions)
+}
+
+/// A fix crustc applies to a printf argument instead of reporting it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fix {
+    /// Cast to this (libc) type. Only used when the cast can't lose information.
+    Cast(&'static str),
+    /// `*const CStr` is a fat pointer, so pass `(*arg).as_ptr()` instead.
+    CStrAsPtr,
+    /// A string literal is a `&str`, which can't go through varargs, so make it a C string.
+    CStrLiteral,
+}
+
+/// Checks an argument against what its conversion expects. Errors come with a hint on
+/// how to print the argument instead, if there is one.
+pub fn check_arg(expected: Expected, arg: Option<PrintfArg>, target: CTarget) -> Result<Option<Fix>, Option<String>> {
+    let Some(arg) = arg else {
+        return Err(None);
+    };
+
+    let hint = || printf_conversion(&FormatSpec::default(), arg.class).ok().map(|conversion| format!("use `{conversion}` to print this argument"));
+
+    match expected {
+        Expected::Int { bits, c_type } if arg.class.is_integer() || matches!(arg.class, PrintfClass::Char | PrintfClass::Bool) => {
+            let arg_bits = target.bits(arg.class);
+            if arg_bits > bits {
+                Err(hint())
+            } else if arg_bits < bits || arg.promote || arg.class == PrintfClass::Bool {
+                Ok(Some(Fix::Cast(c_type)))
+            } else {
+                Ok(None)
+            }
+        }
+        Expected::Double if arg.class == PrintfClass::Double => Ok(arg.promote.then_some(Fix::Cast("c_double"))),
+        Expected::CString | Expected::Pointer if arg.class == PrintfClass::CStr => Ok(Some(Fix::CStrAsPtr)),
+        Expected::CString if arg.class == PrintfClass::CString => Ok(None),
+        Expected::Pointer if matches!(arg.class, PrintfClass::CString | PrintfClass::Pointer) => Ok(None),
+        _ => Err(hint()),
+    }
+}
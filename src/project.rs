@@ -0,0 +1,274 @@
+//! Projects: the crates a `crust.build` manifest describes, built together by `crustc build`.
+//!
+//! There's no cargo in Crust, so the manifest is as small as it can be. Each crate is a
+//! section saying what kind it is and what it's called, followed by its settings:
+//!
+//! ```text
+//! # A library, and a program that uses it.
+//! [lib mathx]
+//! source = mathx.crs
+//!
+//! [bin calc]
+//! deps = mathx
+//! libs = m
+//! flags = -O --panic-handler=abort
+//! ```
+//!
+//! The kinds are the `--crate-type`s: `bin`, `lib`, `staticlib` and `cdylib`. `source`
+//! defaults to `NAME.crs`, and is relative to the manifest, as is everything crustc writes:
+//! `build/NAME` for programs and `build/libNAME.rlib`, `.a` or `.so` for libraries. `deps`
+//! are `lib` crates from the same manifest, which are built first. `libs` are native libraries to
+//! link, and `flags` are crustc flags for just this crate, after the ones `crustc build` got.
+
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli,
+    diagnostics::{self, Diagnostic},
+    session::{self, Mode, Session},
+    CrateType,
+};
+
+/// The manifest `crustc build` reads when it isn't given one.
+pub const DEFAULT_MANIFEST: &str = "crust.build";
+
+/// Where the outputs go, next to the manifest.
+const BUILD_DIR: &str = "build";
+
+/// One crate in the manifest. Spans index into the manifest's text.
+struct Target {
+    crate_type: CrateType,
+    name: String,
+    name_span: Range<usize>,
+    source: Option<String>,
+    deps: Vec<(String, Range<usize>)>,
+    libs: Vec<String>,
+    flags: Vec<String>,
+    flags_span: Range<usize>,
+}
+
+impl Target {
+    fn output(&self, build_dir: &Path) -> PathBuf {
+        build_dir.join(self.crate_type.file_name(&self.name))
+    }
+}
+
+/// The words of `text`, with their spans, given that `text` starts at `offset`.
+fn words(text: &str, offset: usize) -> impl Iterator<Item = (Range<usize>, &str)> {
+    text.split_whitespace().map(move |word| {
+        let start = offset + (word.as_ptr() as usize - text.as_ptr() as usize);
+        (start..start + word.len(), word)
+    })
+}
+
+fn is_crate_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse(text: &str) -> Result<Vec<Target>, Box<Diagnostic>> {
+    let mut targets: Vec<Target> = vec![];
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let line = line.split('#').next().unwrap_or_default().trim_end();
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim_start();
+        let line_span = start + indent..start + indent + line.len();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let Some(header) = header.strip_suffix(']') else {
+                return Err(Box::new(Diagnostic::error("unclosed crate header").label(line_span, "expected `]` at the end")));
+            };
+            let header_words: Vec<_> = words(header, line_span.start + 1).collect();
+            let [(kind_span, kind), (name_span, name)] = header_words.as_slice() else {
+                return Err(Box::new(Diagnostic::error("invalid crate header")
+                    .label(line_span, "expected a kind and a name")
+                    .help("crates start with `[bin NAME]` or `[lib NAME]`")));
+            };
+            let Ok(crate_type) = kind.parse() else {
+                let label = "expected `bin`, `lib`, `staticlib` or `cdylib`";
+                return Err(Box::new(Diagnostic::error("unknown crate kind").label(kind_span.clone(), label)));
+            };
+            if !is_crate_name(name) {
+                return Err(Box::new(Diagnostic::error("invalid crate name")
+                    .label(name_span.clone(), "expected letters, digits and underscores")));
+            }
+            if let Some(first) = targets.iter().find(|target| target.name == *name) {
+                return Err(Box::new(Diagnostic::error(format!("crate `{name}` is defined twice"))
+                    .label(name_span.clone(), "defined again here")
+                    .secondary_label(first.name_span.clone(), "first defined here")));
+            }
+            targets.push(Target {
+                crate_type,
+                name: name.to_string(),
+                name_span: name_span.clone(),
+                source: None,
+                deps: vec![],
+                libs: vec![],
+                flags: vec![],
+                flags_span: name_span.clone(),
+            });
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(Box::new(Diagnostic::error("expected a setting").label(line_span, "expected `KEY = VALUE`")));
+        };
+        let key_span = line_span.start..line_span.start + key.trim_end().len();
+        let value_start = line_span.start + key.len() + 1;
+        let value_span = value_start + (value.len() - value.trim_start().len())..line_span.end;
+        let Some(target) = targets.last_mut() else {
+            return Err(Box::new(Diagnostic::error("setting outside of a crate")
+                .label(key_span, "this isn't under any crate")
+                .help("start a crate with `[bin NAME]` or `[lib NAME]`")));
+        };
+        match key.trim_end() {
+            "source" => target.source = Some(value.trim().to_string()),
+            "deps" => target.deps.extend(words(value, value_start).map(|(span, name)| (name.to_string(), span))),
+            "libs" => target.libs.extend(value.split_whitespace().map(str::to_string)),
+            "flags" => {
+                target.flags.extend(value.split_whitespace().map(str::to_string));
+                target.flags_span = value_span;
+            }
+            _ => {
+                return Err(Box::new(Diagnostic::error("unknown setting")
+                    .label(key_span, "expected `source`, `deps`, `libs` or `flags`")));
+            }
+        }
+    }
+    Ok(targets)
+}
+
+/// The order to build `targets` in, so that every library comes before what uses it.
+fn build_order(targets: &[Target]) -> Result<Vec<usize>, Box<Diagnostic>> {
+    let index: HashMap<&str, usize> = targets.iter().enumerate().map(|(i, target)| (target.name.as_str(), i)).collect();
+    for target in targets {
+        for (dep, span) in &target.deps {
+            let Some(&i) = index.get(dep.as_str()) else {
+                return Err(Box::new(Diagnostic::error(format!("no crate named `{dep}`")).label(span.clone(), "not in this manifest")));
+            };
+            if targets[i].crate_type != CrateType::Lib {
+                return Err(Box::new(Diagnostic::error(format!("`{dep}` isn't a Rust library"))
+                    .label(span.clone(), "only `lib` crates can be dependencies")
+                    .secondary_label(targets[i].name_span.clone(), "defined here")));
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Unvisited,
+        Visiting,
+        Done,
+    }
+
+    fn visit(i: usize, targets: &[Target], index: &HashMap<&str, usize>, states: &mut [State], order: &mut Vec<usize>) -> Result<(), Box<Diagnostic>> {
+        states[i] = State::Visiting;
+        for (dep, span) in &targets[i].deps {
+            let dep = index[dep.as_str()];
+            match states[dep] {
+                State::Done => {}
+                State::Unvisited => visit(dep, targets, index, states, order)?,
+                State::Visiting => {
+                    return Err(Box::new(Diagnostic::error("dependency cycle")
+                        .label(span.clone(), format!("`{}` depends on `{}`, which depends on it", targets[i].name, targets[dep].name))));
+                }
+            }
+        }
+        states[i] = State::Done;
+        order.push(i);
+        Ok(())
+    }
+
+    let mut states = vec![State::Unvisited; targets.len()];
+    let mut order = vec![];
+    for i in 0..targets.len() {
+        if states[i] == State::Unvisited {
+            visit(i, targets, &index, &mut states, &mut order)?;
+        }
+    }
+    Ok(order)
+}
+
+/// Builds every crate in the manifest `options.input`, returning the exit code. `args` are
+/// `crustc build`'s, whose flags apply to every crate.
+pub fn build(args: &[String], options: &cli::Options) -> i32 {
+    let manifest_path = Path::new(&options.input);
+    let Ok(text) = fs::read_to_string(manifest_path) else {
+        cli::report_error_failed_to_open_manifest(args, options.input_index, &options.input);
+        return rustc_driver::EXIT_FAILURE;
+    };
+    let origin = Some(options.input.as_str());
+    let parsed = parse(&text).and_then(|targets| build_order(&targets).map(|order| (targets, order)));
+    let (targets, order) = match parsed {
+        Ok(parsed) => parsed,
+        Err(diagnostic) => {
+            diagnostics::report(options.error_format, &diagnostic, &text, origin);
+            return rustc_driver::EXIT_FAILURE;
+        }
+    };
+
+    let root = manifest_path.parent().unwrap_or(Path::new(""));
+    let build_dir = root.join(BUILD_DIR);
+    if let Err(err) = fs::create_dir_all(&build_dir) {
+        session::report_error_write_output(options.error_format, &build_dir, &err);
+        return rustc_driver::EXIT_FAILURE;
+    }
+
+    for i in order {
+        let target = &targets[i];
+        let source = root.join(target.source.clone().unwrap_or_else(|| format!("{}.crs", target.name)));
+
+        // Each crate is built as though crustc had been run on it with all the flags.
+        let mut target_args = vec![args[0].clone()];
+        target_args.extend(args.iter().enumerate().skip(2).filter(|&(i, _)| i != options.input_index).map(|(_, arg)| arg.clone()));
+        target_args.extend(target.flags.iter().cloned());
+        for lib in &target.libs {
+            target_args.push(format!("-l{lib}"));
+        }
+        target_args.push(source.display().to_string());
+
+        let mut target_options = match cli::parse(&target_args) {
+            Ok(cli::Command::Compile(options)) => options,
+            Ok(_) => {
+                let diagnostic = Diagnostic::error("not a build flag")
+                    .label(target.flags_span.clone(), "crates can only be given flags for building them");
+                diagnostics::report(options.error_format, &diagnostic, &text, origin);
+                return rustc_driver::EXIT_FAILURE;
+            }
+            Err(err) => {
+                cli::report_error(&target_args, &err);
+                return rustc_driver::EXIT_FAILURE;
+            }
+        };
+        target_options.crate_type = target.crate_type;
+        target_options.crate_name = Some(target.name.clone());
+        target_options.emit = vec![cli::Emit::Bin];
+        target_options.output = Some(target.output(&build_dir));
+        target_options.externs = target.deps
+            .iter()
+            .map(|(dep, _)| (dep.clone(), targets.iter().find(|t| t.name == *dep).expect("dependencies were checked").output(&build_dir)))
+            .collect();
+        // Where rustc finds what the dependencies depend on in turn.
+        target_options.link_paths.push(format!("dependency={}", build_dir.display()));
+
+        let Some(session) = Session::new(*target_options, Mode::Build) else {
+            return rustc_driver::EXIT_FAILURE;
+        };
+        let status = session.build(&target_args);
+        if status != rustc_driver::EXIT_SUCCESS {
+            return status;
+        }
+    }
+    rustc_driver::EXIT_SUCCESS
+}
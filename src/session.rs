@@ -0,0 +1,228 @@
+//! What a crustc invocation sets up once and then builds with: the options, and the libc
+//! rlib to build against. Building again from the same session is what `--watch` does each
+//! time the source changes.
+
+use std::{
+    env, fs, io,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::Command,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    cli,
+    diagnostics::{self, Diagnostic, ErrorFormat},
+    driver, libc_rlib, transpile, TranspileOptions,
+};
+
+/// How often `--watch` looks at the source for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What a session does with the program once it's transpiled.
+pub enum Mode {
+    /// Write the outputs the options ask for.
+    Build,
+    /// Report what building would, without writing anything.
+    Check,
+    /// Build into a temporary directory, and run the program with these arguments.
+    Run(Vec<String>),
+}
+
+pub struct Session {
+    pub options: cli::Options,
+    libc_rlib: PathBuf,
+    mode: Mode,
+}
+
+pub(crate) fn report_error_write_output(format: ErrorFormat, path: &Path, err: &io::Error) {
+    let diagnostic = Diagnostic::error(format!("couldn't write `{}`", path.display()))
+        .note(err.to_string());
+    diagnostics::report(format, &diagnostic, "", None);
+}
+
+impl Session {
+    /// Sets up to do what `mode` says with the program. Returns `None` once it has reported
+    /// why it can't.
+    pub fn new(mut options: cli::Options, mode: Mode) -> Option<Self> {
+        // A check writes nothing, so it can't build the libc crate either.
+        let libc_rlib = if let Mode::Check = mode {
+            libc_rlib::locate_built(options.libc_path.as_deref(), options.target.as_deref())
+        } else {
+            libc_rlib::locate(options.libc_path.as_deref(), options.target.as_deref(), options.error_format)
+        };
+        let libc_rlib = match libc_rlib {
+            Ok(rlib) => rlib,
+            Err(err) => {
+                diagnostics::report(options.error_format, &err.diagnostic(), "", None);
+                return None;
+            }
+        };
+        if let Mode::Run(_) = mode {
+            options.emit = vec![cli::Emit::Bin];
+            options.output = Some(run_dir().join(options.output_stem().file_stem().unwrap_or_default()));
+        }
+        Some(Self { options, libc_rlib, mode })
+    }
+
+    /// Builds or checks once, and under `crustc run` runs the program, returning the exit code.
+    pub fn build(&self, args: &[String]) -> i32 {
+        let Mode::Run(program_args) = &self.mode else {
+            return self.compile(args);
+        };
+
+        // The binary is built into a directory of its own, which goes away after the run.
+        let dir = run_dir();
+        if let Err(err) = fs::create_dir_all(&dir) {
+            report_error_write_output(self.options.error_format, &dir, &err);
+            return rustc_driver::EXIT_FAILURE;
+        }
+        let mut status = self.compile(args);
+        if status == rustc_driver::EXIT_SUCCESS {
+            let binary = self.options.output_path(cli::Emit::Bin);
+            status = match Command::new(&binary).args(program_args).status() {
+                // Like a shell, report death by a signal as 128 plus the signal.
+                Ok(exit) => exit.code().unwrap_or_else(|| 128 + exit.signal().unwrap_or(0)),
+                Err(err) => {
+                    let diagnostic = Diagnostic::error(format!("couldn't run `{}`", binary.display()))
+                        .note(err.to_string());
+                    diagnostics::report(self.options.error_format, &diagnostic, "", None);
+                    rustc_driver::EXIT_FAILURE
+                }
+            };
+        }
+        let _ = fs::remove_dir_all(&dir);
+        status
+    }
+
+    /// Builds, and then again every time the source changes, until crustc is killed.
+    ///
+    /// Crust has no modules, so the source file is all there is to watch. It's polled rather
+    /// than waited on, which also copes with editors that save by replacing the file.
+    pub fn watch(&self, args: &[String]) -> ! {
+        let mut watcher = Watcher::new(&self.options.input);
+        loop {
+            self.build(args);
+            // JSON output has nowhere to say this.
+            if self.options.error_format == ErrorFormat::Human {
+                eprintln!("watching `{}` for changes...", self.options.input);
+            }
+            while !watcher.changed() {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+
+    /// Transpiles and builds what the options ask for, or only checks that it would build,
+    /// returning the exit code.
+    fn compile(&self, args: &[String]) -> i32 {
+        let options = &self.options;
+        let check = matches!(self.mode, Mode::Check);
+        let source = if options.reads_stdin() {
+            let Ok(source) = io::read_to_string(io::stdin()) else {
+                cli::report_error_failed_to_read_stdin(args, options.input_index);
+                return rustc_driver::EXIT_FAILURE;
+            };
+            source
+        } else {
+            let Ok(source) = fs::read_to_string(&options.input) else {
+                cli::report_error_failed_to_open_source_file(args, options.input_index);
+                return rustc_driver::EXIT_FAILURE;
+            };
+            source
+        };
+
+        let transpile_options = TranspileOptions {
+            filename: options.source_name().to_string(),
+            panic_handler: options.panic_handler,
+            crate_type: options.crate_type,
+            externs: options.externs.clone(),
+            link_paths: options.link_paths.clone(),
+            libc_path: Some(self.libc_rlib.clone()),
+            target: options.target.clone(),
+            // Errors in what crustc adds quote the generated Rust, so a check formats it as a
+            // build would.
+            fmt: options.fmt,
+            rustfmt: options.rustfmt,
+            c_header: options.emits(cli::Emit::CHeader) && !check,
+            error_format: Some(options.error_format),
+        };
+        let Ok(output) = transpile(&source, &transpile_options) else {
+            return rustc_driver::EXIT_FAILURE;
+        };
+        if check {
+            return driver::check(&output.rust, options, &source, &self.libc_rlib, output.span_map());
+        }
+
+        if options.stdout {
+            print!("{}", output.rust);
+        }
+        let keep_rs = options.emits(cli::Emit::Rs) && !options.stdout;
+        let builds = options.emit.iter().any(|kind| kind.rustc_name().is_some());
+        if keep_rs {
+            let rs_path = options.output_path(cli::Emit::Rs);
+            if let Err(err) = fs::write(&rs_path, &output.rust) {
+                report_error_write_output(options.error_format, &rs_path, &err);
+                return rustc_driver::EXIT_FAILURE;
+            }
+        }
+        if let Some(header) = &output.c_header {
+            let header_path = options.output_path(cli::Emit::CHeader);
+            if let Err(err) = fs::write(&header_path, header) {
+                report_error_write_output(options.error_format, &header_path, &err);
+                return rustc_driver::EXIT_FAILURE;
+            }
+        }
+        if !builds {
+            return rustc_driver::EXIT_SUCCESS;
+        }
+
+        // Debug info follows the lines of whatever rustc compiles, so `-g` builds from a copy
+        // laid out along the source, which only ever exists in memory.
+        let (generated, span_map) = if options.debuginfo {
+            output.source_layout(&source)
+        } else {
+            (output.rust.clone(), output.span_map())
+        };
+        let rs_path = options.output_path(cli::Emit::Rs);
+        let kept = (keep_rs && !options.debuginfo).then_some(rs_path.as_path());
+        driver::build_outputs(&generated, kept, options, &source, &self.libc_rlib, span_map)
+    }
+}
+
+/// Notices when a file is modified, by comparing its modification time with the last one
+/// seen.
+pub struct Watcher {
+    path: PathBuf,
+    last: Option<SystemTime>,
+}
+
+impl Watcher {
+    /// Watches `path` for changes from how it is now.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let last = Self::modified(&path);
+        Self { path, last }
+    }
+
+    /// Whether the file has been modified since it was last looked at. While it's missing,
+    /// partway through being replaced, it hasn't been: the change is seen once it's back.
+    pub fn changed(&mut self) -> bool {
+        let current = Self::modified(&self.path);
+        if current.is_none() || current == self.last {
+            return false;
+        }
+        self.last = current;
+        true
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+}
+
+/// Where `crustc run` builds the program.
+fn run_dir() -> PathBuf {
+    env::temp_dir().join(format!("crustc-run-{}", std::process::id()))
+}
@@ -0,0 +1,369 @@
+//! Mapping the generated Rust back to the `.crs` it was compiled from.
+//!
+//! Crustc lexes the source with proc-macro2 and gives the identifiers and literals it emits
+//! the spans of the tokens they came from. Once the generated file is printed (and maybe
+//! reformatted), its tokens are lined up with the emitted ones to get a [`SpanMap`], which
+//! [`Remap`] uses to point rustc's diagnostics about the generated file at the `.crs` instead,
+//! and `-g` builds use to lay the generated file out along the lines of the source.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
+
+use proc_macro2::{Delimiter, Span, TokenStream, TokenTree};
+use rustc_errors::{
+    emitter::{DynEmitter, Emitter},
+    registry::Registry,
+    translation::Translate,
+    DiagInner, FluentBundle, MultiSpan, Suggestions,
+};
+use rustc_session::parse::ParseSess;
+use rustc_span::{source_map::SourceMap, BytePos, FileName, SourceFile};
+
+use crate::{comments, diagnostics::{self, ErrorFormat}};
+
+/// The tokens of a `.crs` file, by the byte they start at.
+///
+/// proc-macro2 keeps what it has lexed in a thread-local, so this has to be made on the
+/// thread the spans are used on, which is rustc's and not ours.
+#[derive(Default)]
+pub struct SourceSpans {
+    starts: HashMap<usize, Span>,
+    /// Any token of the source, to tell its spans apart from ones made up by crustc.
+    anchor: Option<Span>,
+}
+
+impl SourceSpans {
+    pub fn new(source: &str) -> Self {
+        let mut tokens = vec![];
+        if let Ok(stream) = TokenStream::from_str(source) {
+            flatten(stream, &mut tokens);
+        }
+        Self {
+            anchor: tokens.first().map(|(_, span)| *span),
+            starts: tokens.into_iter().map(|(_, span)| (span.byte_range().start, span)).collect(),
+        }
+    }
+
+    /// The span of the source token starting at byte `start`, or the call site if there's none.
+    pub fn span_at(&self, start: Option<usize>) -> Span {
+        start.and_then(|start| self.starts.get(&start)).copied().unwrap_or_else(Span::call_site)
+    }
+
+    /// Where in the source `span` is, if it's one of ours.
+    fn source_range(&self, span: Span) -> Option<Range<usize>> {
+        // Spans only join within one lexed file, so this also turns away the call site and
+        // anything crustc parsed from a string of its own.
+        self.anchor?.join(span)?;
+        Some(span.byte_range())
+    }
+
+    /// The stretch of the source that `tokens` were compiled from, if any of them were.
+    pub fn source_range_of(&self, tokens: TokenStream) -> Option<Range<usize>> {
+        let mut flat = vec![];
+        flatten(tokens, &mut flat);
+        flat.into_iter()
+            .filter_map(|(_, span)| self.source_range(span))
+            .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end))
+    }
+
+    /// Flattens the tokens crustc emitted, keeping where in the source each one came from.
+    pub fn emitted_tokens(&self, tokens: TokenStream) -> Vec<EmittedToken> {
+        let mut flat = vec![];
+        flatten(tokens, &mut flat);
+        flat.into_iter()
+            .map(|(text, span)| EmittedToken { source: self.source_range(span), text })
+            .collect()
+    }
+}
+
+/// A token of the generated file as crustc emitted it, before it was printed.
+pub struct EmittedToken {
+    text: String,
+    source: Option<Range<usize>>,
+}
+
+/// Byte ranges of the generated file, each paired with the source bytes it was compiled from.
+#[derive(Default)]
+pub struct SpanMap {
+    entries: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl SpanMap {
+    /// Lines up the tokens crustc emitted with the printed `generated` file.
+    ///
+    /// Formatting only moves tokens around, save for the odd trailing comma or semicolon,
+    /// so we skip those and stop at the first real mismatch rather than guess.
+    pub fn new(emitted: &[EmittedToken], generated: &str) -> Self {
+        let mut printed = vec![];
+        if let Ok(stream) = TokenStream::from_str(generated) {
+            flatten(stream, &mut printed);
+        }
+
+        let is_separator = |text: &str| matches!(text, "," | ";");
+        // Doc comments lex as attributes, but any in the printed file were put back as comments.
+        let comments = comments::collect(generated);
+        let is_comment = |span: &Span| {
+            let start = span.byte_range().start;
+            let i = comments.partition_point(|comment| comment.end <= start);
+            comments.get(i).is_some_and(|comment| comment.start <= start)
+        };
+        let mut entries = vec![];
+        let (mut i, mut j) = (0, 0);
+        while let (Some(emitted), Some((text, span))) = (emitted.get(i), printed.get(j)) {
+            if is_comment(span) {
+                j += 1;
+            } else if emitted.text == *text {
+                if let Some(source) = &emitted.source {
+                    entries.push((span.byte_range(), source.clone()));
+                }
+                i += 1;
+                j += 1;
+            } else if is_separator(text) {
+                j += 1;
+            } else if is_separator(&emitted.text) {
+                i += 1;
+            } else {
+                break;
+            }
+        }
+        Self { entries }
+    }
+
+    /// The source bytes covering every mapped token in `generated`.
+    pub fn lookup(&self, generated: Range<usize>) -> Option<Range<usize>> {
+        self.entries
+            .iter()
+            .filter(|(printed, _)| if generated.is_empty() {
+                printed.contains(&generated.start)
+            } else {
+                printed.start < generated.end && generated.start < printed.end
+            })
+            .map(|(_, source)| source.clone())
+            .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end))
+    }
+
+    /// Where in the generated file the first token compiled from at or after `source_offset`
+    /// ended up.
+    pub fn generated_after(&self, source_offset: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .filter(|(_, source)| source.start >= source_offset)
+            .min_by_key(|(printed, source)| (source.start, printed.start))
+            .map(|(printed, _)| printed.start)
+    }
+
+    /// Whether any token in `generated` was compiled from the source.
+    pub fn maps_any(&self, generated: Range<usize>) -> bool {
+        self.entries.iter().any(|(printed, _)| generated.contains(&printed.start))
+    }
+
+    /// Lays `generated` out again so every line starts on the line of `source` it came from,
+    /// joining it onto the line before where that would mean going back. Compiled with `-g`,
+    /// the debug info then matches the `.crs` line for line, so debuggers can show that
+    /// instead of the generated file.
+    pub fn source_layout(&self, generated: &str, source: &str) -> String {
+        // Nobody reads this copy, and comments only get in the way of joining lines.
+        let generated = &comments::blank(generated);
+        let tokens = token_ranges(generated);
+        // Newlines inside a token, like a multi-line string, aren't ours to move.
+        let kept: HashSet<usize> = tokens
+            .iter()
+            .flat_map(|token| generated[token.clone()].match_indices('\n').map(|(i, _)| token.start + i))
+            .collect();
+        let source_newlines: Vec<usize> = source.match_indices('\n').map(|(i, _)| i).collect();
+        let source_line = |offset: usize| source_newlines.partition_point(|&newline| newline < offset) + 1;
+
+        let mut out = String::new();
+        let mut line = 1;
+        let mut start = 0;
+        let ends = generated.match_indices('\n').map(|(i, _)| i).chain([generated.len()]);
+        for end in ends.filter(|end| !kept.contains(end)) {
+            let chunk = start..end;
+            start = end + 1;
+            let text = &generated[chunk.clone()];
+            if text.trim().is_empty() {
+                continue;
+            }
+
+            let first = self.entries.partition_point(|(printed, _)| printed.start < chunk.start);
+            let target = self.entries
+                .get(first)
+                .filter(|(printed, _)| printed.start < chunk.end)
+                .map(|(_, source)| source_line(source.start));
+            match target {
+                Some(target) if target > line => {
+                    out.extend(std::iter::repeat_n('\n', target - line));
+                    out.push_str(text);
+                    line = target;
+                }
+                _ if out.is_empty() => out.push_str(text),
+                _ => {
+                    out.push(' ');
+                    out.push_str(text.trim_start());
+                }
+            }
+            line += text.matches('\n').count();
+        }
+        out.push('\n');
+        out
+    }
+}
+
+/// Where each token of `text` is, or nothing if it doesn't lex.
+pub fn token_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut tokens = vec![];
+    if let Ok(stream) = TokenStream::from_str(text) {
+        flatten(stream, &mut tokens);
+    }
+    tokens.iter().map(|(_, span)| span.byte_range()).collect()
+}
+
+fn flatten(tokens: TokenStream, out: &mut Vec<(String, Span)>) {
+    for token in tokens {
+        match token {
+            TokenTree::Group(group) => {
+                let (open, close) = match group.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => {
+                        flatten(group.stream(), out);
+                        continue;
+                    }
+                };
+                out.push((open.to_string(), group.span_open()));
+                flatten(group.stream(), out);
+                out.push((close.to_string(), group.span_close()));
+            }
+            TokenTree::Ident(ident) => out.push((ident.to_string(), ident.span())),
+            TokenTree::Punct(punct) => out.push((punct.as_char().to_string(), punct.span())),
+            TokenTree::Literal(lit) => out.push((lit.to_string(), lit.span())),
+        }
+    }
+}
+
+/// What rustc needs to report errors in a generated file against its `.crs`.
+pub struct Remap {
+    pub map: SpanMap,
+    pub generated: PathBuf,
+    /// What the source is called, and what's in it. It may not be a file at all.
+    pub source: PathBuf,
+    pub source_text: String,
+    pub format: ErrorFormat,
+}
+
+impl Remap {
+    /// Swaps the session's emitter for one that rewrites spans in the generated file.
+    pub fn install(self, psess: &mut ParseSess) {
+        let source_map = psess.clone_source_map();
+        let source = source_map.new_source_file(self.source.into(), self.source_text);
+        let inner = diagnostics::rustc_emitter(self.format, source_map.clone());
+        psess.dcx().set_emitter(Box::new(RemapEmitter {
+            inner,
+            source_map,
+            map: self.map,
+            generated: self.generated,
+            source,
+        }));
+    }
+}
+
+struct RemapEmitter {
+    inner: Box<DynEmitter>,
+    source_map: Arc<SourceMap>,
+    map: SpanMap,
+    generated: PathBuf,
+    source: Arc<SourceFile>,
+}
+
+impl RemapEmitter {
+    /// Where in the source `span` came from, if it's in the generated file and we know.
+    fn remap(&self, span: rustc_span::Span) -> Option<rustc_span::Span> {
+        if span.is_dummy() {
+            return None;
+        }
+        let data = span.data();
+        let file = self.source_map.lookup_source_file(data.lo);
+        let in_generated = matches!(&file.name, FileName::Real(real) if real.local_path() == Some(&self.generated));
+        if !in_generated || data.hi > file.end_position() {
+            return None;
+        }
+
+        let start = (data.lo - file.start_pos).0 as usize;
+        let end = (data.hi - file.start_pos).0 as usize;
+        let source = self.map.lookup(start..end)?;
+        let pos = |offset: usize| self.source.start_pos + BytePos(offset as u32);
+        Some(span.with_lo(pos(source.start)).with_hi(pos(source.end)))
+    }
+
+    fn remap_multispan(&self, multispan: &mut MultiSpan) {
+        let mut spans = multispan.primary_spans().to_vec();
+        spans.extend(multispan.span_labels().into_iter().map(|label| label.span));
+        spans.dedup();
+        for span in spans {
+            if let Some(remapped) = self.remap(span) {
+                multispan.replace(span, remapped);
+            }
+        }
+    }
+}
+
+impl Translate for RemapEmitter {
+    fn fluent_bundle(&self) -> Option<&FluentBundle> {
+        self.inner.fluent_bundle()
+    }
+
+    fn fallback_fluent_bundle(&self) -> &FluentBundle {
+        self.inner.fallback_fluent_bundle()
+    }
+}
+
+impl Emitter for RemapEmitter {
+    fn emit_diagnostic(&mut self, mut diag: DiagInner, registry: &Registry) {
+        self.remap_multispan(&mut diag.span);
+        for child in &mut diag.children {
+            self.remap_multispan(&mut child.span);
+        }
+        let suggestions: &mut [_] = match &mut diag.suggestions {
+            Suggestions::Enabled(suggestions) => suggestions,
+            Suggestions::Sealed(suggestions) => suggestions,
+            Suggestions::Disabled => &mut [],
+        };
+        // A substitution can only be moved if all of it can, or it would span two files.
+        for substitution in suggestions.iter_mut().flat_map(|suggestion| &mut suggestion.substitutions) {
+            let remapped: Option<Vec<_>> = substitution.parts.iter().map(|part| self.remap(part.span)).collect();
+            for (part, span) in substitution.parts.iter_mut().zip(remapped.into_iter().flatten()) {
+                part.span = span;
+            }
+        }
+        if let Some(sort_span) = self.remap(diag.sort_span) {
+            diag.sort_span = sort_span;
+        }
+        self.inner.emit_diagnostic(diag, registry);
+    }
+
+    fn emit_artifact_notification(&mut self, path: &std::path::Path, artifact_type: &str) {
+        self.inner.emit_artifact_notification(path, artifact_type);
+    }
+
+    fn emit_future_breakage_report(&mut self, diags: Vec<DiagInner>, registry: &Registry) {
+        self.inner.emit_future_breakage_report(diags, registry);
+    }
+
+    fn should_show_explain(&self) -> bool {
+        self.inner.should_show_explain()
+    }
+
+    fn supports_color(&self) -> bool {
+        self.inner.supports_color()
+    }
+
+    fn source_map(&self) -> Option<&SourceMap> {
+        self.inner.source_map()
+    }
+}
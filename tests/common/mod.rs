@@ -0,0 +1,143 @@
+//! What the test suites under `tests/` share: running crustc, checking its output against
+//! committed files, reporting results the way libtest does, and scratch directories.
+
+// Each suite uses only some of this.
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, ExitCode, Output},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub struct Config {
+    /// Write what crustc produced to the expected files instead of comparing with them.
+    pub bless: bool,
+    filters: Vec<String>,
+}
+
+impl Config {
+    pub fn from_args() -> Self {
+        let mut config = Self { bless: false, filters: vec![] };
+        for arg in env::args().skip(1) {
+            match arg.as_str() {
+                "--bless" => config.bless = true,
+                // Whatever else cargo passes on to test harnesses doesn't mean anything here.
+                arg if arg.starts_with('-') => {}
+                filter => config.filters.push(filter.to_string()),
+            }
+        }
+        config
+    }
+
+    fn selects(&self, name: &str) -> bool {
+        self.filters.is_empty() || self.filters.iter().any(|filter| name.contains(filter.as_str()))
+    }
+}
+
+/// Runs `test` for each of `names` that the filters select, and reports how it went.
+pub fn run(config: &Config, suite: &str, names: &[String], test: impl Fn(&str) -> Result<(), String>) -> ExitCode {
+    let mut failures = vec![];
+    let mut ran = 0;
+    for name in names.iter().filter(|name| config.selects(name)) {
+        ran += 1;
+        match test(name) {
+            Ok(()) => println!("test {suite}/{name} ... ok"),
+            Err(why) => {
+                println!("test {suite}/{name} ... FAILED");
+                failures.push((name, why));
+            }
+        }
+    }
+
+    for (name, why) in &failures {
+        println!("\n---- {suite}/{name} ----\n{why}");
+    }
+    let result = if failures.is_empty() { "ok" } else { "FAILED" };
+    println!("\ntest result: {result}. {} passed; {} failed", ran - failures.len(), failures.len());
+    if failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        if !config.bless {
+            println!("\nto update the expected output, run `cargo test --test {suite} -- --bless`");
+        }
+        ExitCode::FAILURE
+    }
+}
+
+/// The entries of `dir` that `keep` accepts, sorted.
+pub fn entries(dir: &Path, keep: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|err| panic!("couldn't read {}: {err}", dir.display()))
+        .map(|entry| entry.expect("directory entries should be readable").path())
+        .filter(|path| keep(path))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Runs crustc in `dir`, so that paths in what it prints don't depend on where we are.
+pub fn crustc(dir: &Path, args: &[&str]) -> Result<Output, String> {
+    Command::new(env!("CARGO_BIN_EXE_crustc"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|err| format!("couldn't run crustc: {err}"))
+}
+
+/// A fresh directory for build outputs, removed when it's dropped.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(suite: &str, name: &str) -> Result<Self, String> {
+        // Tests run in parallel, and may well want directories with the same name.
+        static DIRS: AtomicUsize = AtomicUsize::new(0);
+        let unique = DIRS.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("crustc-{suite}-{}-{unique}-{name}", std::process::id()));
+        fs::create_dir_all(&path).map_err(|err| format!("couldn't create {}: {err}", path.display()))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Checks `actual` against the file at `expected_path`, or with `--bless`, writes it there.
+pub fn check(config: &Config, expected_path: &Path, actual: &str) -> Result<(), String> {
+    if config.bless {
+        return fs::write(expected_path, actual).map_err(|err| format!("couldn't write {}: {err}", expected_path.display()));
+    }
+    let Ok(expected) = fs::read_to_string(expected_path) else {
+        return Err(format!("{} is missing", expected_path.display()));
+    };
+    if expected == actual {
+        return Ok(());
+    }
+    Err(format!("{} doesn't match:\n{}", expected_path.display(), diff(&expected, actual)))
+}
+
+/// The lines that differ between `expected` and `actual`, with the ones around them trimmed.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let prefix = expected.iter().zip(&actual).take_while(|(a, b)| a == b).count();
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut out = format!("@@ line {} @@\n", prefix + 1);
+    for line in &expected[prefix..expected.len() - suffix] {
+        out.push_str(&format!("-{line}\n"));
+    }
+    for line in &actual[prefix..actual.len() - suffix] {
+        out.push_str(&format!("+{line}\n"));
+    }
+    out
+}
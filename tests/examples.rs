@@ -0,0 +1,59 @@
+//! Golden-file tests over `examples/`.
+//!
+//! Every `examples/NAME/NAME.crs` is transpiled, and what comes out is compared with what's
+//! committed next to it: the generated Rust in `NAME.crs.generated.rs` if crustc succeeds, or
+//! its errors in `NAME.crs.stderr` if it doesn't. Examples with a `NAME.expected_stdout` are
+//! also run with `crustc run`, and have their output compared with that.
+//!
+//!     cargo test --test examples                  # check everything
+//!     cargo test --test examples -- hello         # only examples with `hello` in the name
+//!     cargo test --test examples -- --bless       # update the committed files instead
+
+mod common;
+
+use std::{fs, path::Path, process::ExitCode};
+
+use common::Config;
+
+fn main() -> ExitCode {
+    let config = Config::from_args();
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let names: Vec<String> = common::entries(&examples, Path::is_dir)
+        .iter()
+        .map(|dir| dir.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    common::run(&config, "examples", &names, |name| run_example(&config, &examples.join(name), name))
+}
+
+fn run_example(config: &Config, dir: &Path, name: &str) -> Result<(), String> {
+    let source = format!("{name}.crs");
+    let generated_path = dir.join(format!("{source}.generated.rs"));
+    let stderr_path = dir.join(format!("{source}.stderr"));
+
+    let output = common::crustc(dir, &[&source, "--emit=rs", "--stdout"])?;
+    let (expected_path, actual, stale_path) = if output.status.success() {
+        (generated_path, output.stdout, stderr_path)
+    } else {
+        (stderr_path, output.stderr, generated_path)
+    };
+    if config.bless {
+        let _ = fs::remove_file(&stale_path);
+    } else if stale_path.exists() {
+        let outcome = if output.status.success() { "succeeded" } else { "failed" };
+        return Err(format!("crustc {outcome}, but {} expects otherwise", stale_path.display()));
+    }
+    common::check(config, &expected_path, &String::from_utf8_lossy(&actual))?;
+
+    let stdout_path = dir.join(format!("{name}.expected_stdout"));
+    if stdout_path.exists() {
+        if !output.status.success() {
+            return Err(format!("{} exists, but the example doesn't transpile", stdout_path.display()));
+        }
+        let run = common::crustc(dir, &["run", &source])?;
+        if !run.status.success() {
+            return Err(format!("`crustc run` failed:\n{}", String::from_utf8_lossy(&run.stderr)));
+        }
+        common::check(config, &stdout_path, &String::from_utf8_lossy(&run.stdout))?;
+    }
+    Ok(())
+}
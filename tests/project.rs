@@ -0,0 +1,48 @@
+//! `crustc build` on the projects in `tests/project/`, each copied somewhere it can build.
+
+mod common;
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use common::TempDir;
+
+/// Copies the project `name` into a fresh directory and runs `crustc build` there.
+fn build(name: &str, manifest: &str) -> (TempDir, Output) {
+    let project = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/project").join(name);
+    let temp = TempDir::new("project", name).unwrap();
+    let dir = &temp.0;
+    for entry in fs::read_dir(&project).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+    }
+    fs::write(dir.join("crust.build"), manifest).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crustc")).arg("build").current_dir(dir).output().unwrap();
+    (temp, output)
+}
+
+#[test]
+fn builds_libraries_before_what_uses_them() {
+    let manifest = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/project/calc/crust.build")).unwrap();
+    let (dir, output) = build("calc", &manifest);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.0.join("build/libmathx.rlib").exists());
+    assert!(dir.0.join("build/libgeo.rlib").exists());
+
+    let run = Command::new(dir.0.join("build/calc")).output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "12\n");
+}
+
+#[test]
+fn reports_dependency_cycles_in_the_manifest() {
+    let (dir, output) = build("calc", "[bin calc]\nsource = main.crs\ndeps = geo\n\n[lib geo]\ndeps = mathx\n\n[lib mathx]\ndeps = geo\n");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("error: dependency cycle"), "{stderr}");
+    assert!(stderr.contains("--> crust.build:9:8"), "{stderr}");
+    assert!(!dir.0.join("build").exists());
+}
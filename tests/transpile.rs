@@ -0,0 +1,162 @@
+//! Calling crustc as a library, from source in memory, and as a filter from stdin to stdout.
+
+// Like anything linking crustc, this has to link the compiler it's built on.
+#![feature(rustc_private)]
+
+extern crate rustc_driver;
+
+mod common;
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+use common::TempDir;
+use crustc::{diagnostics::{ErrorFormat, Level}, libc_rlib, transpile, CrateType, TranspileOptions, TranspileOutput};
+
+/// A libc rlib built once for the whole suite, so that nothing lands in the user's cache.
+fn libc_path() -> &'static Path {
+    static RLIB: OnceLock<PathBuf> = OnceLock::new();
+    RLIB.get_or_init(|| {
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("transpile-libc");
+        libc_rlib::build(&dir, None, ErrorFormat::Human).unwrap_or_else(|err| panic!("{err}"))
+    })
+}
+
+/// The default options, with the suite's libc.
+fn options() -> TranspileOptions {
+    TranspileOptions { libc_path: Some(libc_path().to_path_buf()), ..TranspileOptions::default() }
+}
+
+/// Transpiles `source`, which should work, failing the test with the errors if it doesn't.
+fn transpiled(source: &str, options: &TranspileOptions) -> TranspileOutput {
+    transpile(source, options).unwrap_or_else(|errors| {
+        panic!("{}", errors.iter().map(|error| error.title.as_str()).collect::<Vec<_>>().join("\n"))
+    })
+}
+
+#[test]
+fn transpiles_source_from_memory() {
+    let source = "use libc::printf;\n\n// Says hello.\nfn main() {\n    printf!(\"hello\\n\");\n}\n";
+    let output = transpiled(source, &options());
+
+    assert!(output.rust.contains("pub unsafe fn main()"), "{}", output.rust);
+    assert!(output.rust.contains("// Says hello."), "{}", output.rust);
+    assert!(output.warnings.is_empty());
+}
+
+#[test]
+fn transpiles_stdin_to_stdout() {
+    let source = "use libc::printf;\n\n// Says hello.\nfn main() {\n    printf!(\"hello\\n\");\n}\n";
+    let dir = TempDir::new("transpile", "stdin").unwrap();
+    let mut crustc = Command::new(env!("CARGO_BIN_EXE_crustc"))
+        .args(["-", "--stdout", "--libc-path"])
+        .arg(libc_path())
+        .current_dir(&dir.0)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    crustc.stdin.take().unwrap().write_all(source.as_bytes()).unwrap();
+    let output = crustc.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let expected = transpiled(source, &options());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected.rust);
+    // A bare `--stdout` is all the output there is.
+    assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 0);
+}
+
+#[test]
+fn returns_crust_errors_against_the_source() {
+    let source = "fn by_ref(n: &i32) {}\n\nfn main() {}\n";
+    let Err(errors) = transpile(source, &options()) else {
+        panic!("a reference type should be an error");
+    };
+
+    let [error] = errors.as_slice() else {
+        panic!("expected one error, got {}", errors.len());
+    };
+    assert_eq!(error.level, Level::Error);
+    assert_eq!(error.code.as_deref(), Some("C0001"));
+    assert_eq!(&source[error.labels[0].span.clone()], "&i32");
+    assert_eq!(error.suggestions[0].replacement, "*const i32");
+}
+
+#[test]
+fn returns_rustc_errors_against_the_source() {
+    let source = "fn main() {\n    missing();\n}\n";
+    let options = TranspileOptions { filename: "missing.crs".to_string(), ..options() };
+    let Err(errors) = transpile(source, &options) else {
+        panic!("calling a function that doesn't exist should be an error");
+    };
+
+    let [error] = errors.as_slice() else {
+        panic!("expected one error, got {}", errors.len());
+    };
+    assert_eq!(error.code.as_deref(), Some("E0425"));
+    let primary = error.labels.iter().find(|label| label.primary).expect("the error should point at the call");
+    assert_eq!(&source[primary.span.clone()], "missing");
+}
+
+#[test]
+fn leaves_the_runtime_out_of_libraries() {
+    let source = "extern crate core as c;\n\n#[no_mangle]\nfn twice(a: i32) -> i32 {\n    a + a\n}\n";
+    let options = TranspileOptions { crate_type: CrateType::Lib, ..options() };
+    let output = transpiled(source, &options);
+
+    assert!(output.rust.contains("extern crate core as c;"), "{}", output.rust);
+    assert!(output.rust.contains("#[no_mangle]"), "{}", output.rust);
+    // Whatever program the library ends up in brings these.
+    assert!(!output.rust.contains("#[panic_handler]"), "{}", output.rust);
+    assert!(!output.rust.contains("__crust_main"), "{}", output.rust);
+}
+
+#[test]
+fn declares_a_librarys_c_exports() {
+    let source = "use libc::{c_char, c_int};\n\nconst SIDES: i32 = 4;\n\n#[no_mangle]\nstatic mut COUNTER: c_int = 0;\n\n#[no_mangle]\nextern \"C\" fn first(argv: *const *mut c_char) -> *mut c_char {\n    *argv\n}\n\nfn hidden() {}\n";
+    let options = TranspileOptions {
+        filename: "shapes.crs".to_string(),
+        crate_type: CrateType::Staticlib,
+        c_header: true,
+        ..options()
+    };
+    let output = transpiled(source, &options);
+
+    let header = output.c_header.expect("a header was asked for");
+    assert!(header.contains("#ifndef SHAPES_H"), "{header}");
+    assert!(header.contains("#define SIDES ((int32_t)4)"), "{header}");
+    assert!(header.contains("extern int COUNTER;"), "{header}");
+    assert!(header.contains("char *first(char *const *argv);"), "{header}");
+    assert!(!header.contains("hidden"), "{header}");
+}
+
+#[test]
+fn reports_exports_c_cant_declare() {
+    let source = "#[no_mangle]\nextern \"C\" fn first(values: *const [i32; 4]) -> i32 {\n    0\n}\n";
+    let options = TranspileOptions { crate_type: CrateType::Staticlib, c_header: true, ..options() };
+    let Err(errors) = transpile(source, &options) else {
+        panic!("an array parameter should be an error");
+    };
+
+    let [error] = errors.as_slice() else {
+        panic!("expected one error, got {}", errors.len());
+    };
+    assert_eq!(error.code.as_deref(), Some("C0008"));
+    assert_eq!(error.title, "`first` can't be declared in C");
+}
+
+#[test]
+fn declares_variadic_exports() {
+    let source = "#![feature(c_variadic)]\n\nuse libc::{c_char, c_int};\n\n#[no_mangle]\nunsafe extern \"C\" fn log(fmt: *const c_char, mut args: ...) -> c_int {\n    0\n}\n";
+    let options = TranspileOptions { crate_type: CrateType::Staticlib, c_header: true, ..options() };
+    let output = transpiled(source, &options);
+
+    let header = output.c_header.expect("a header was asked for");
+    assert!(header.contains("int log(const char *fmt, ...);"), "{header}");
+}
@@ -0,0 +1,258 @@
+//! UI tests: the errors crustc reports for the programs in `tests/ui/`.
+//!
+//! Each `.crs` says which diagnostics it expects in comments, the way rustc's own UI tests do:
+//!
+//!     fn foo(n: &i32) {} //~ ERROR reference type used
+//!     fn bar(
+//!         n: &i32,
+//!     ) {}
+//!     //~^^ ERROR reference type used
+//!
+//! `//~` is for the line the comment is on, each `^` moves it a line up, `//~|` is for the
+//! same line as the annotation before it, and `//~?` is for diagnostics that aren't at any
+//! line. After that comes ERROR, WARN, HELP or NOTE, and then text that has to appear in
+//! the diagnostic's message, or be its code. Every error and warning has to be annotated;
+//! helps and notes only have to be if the file annotates any of that kind.
+//!
+//! crustc's human-readable output is also compared with `NAME.stderr` next to the test, and
+//! `crustc check` has to print the same.
+//! Extra flags for crustc go in a `//@ compile-flags: ...` line.
+//!
+//!     cargo test --test ui                        # check everything
+//!     cargo test --test ui -- printf              # only tests with `printf` in the name
+//!     cargo test --test ui -- --bless             # update the `.stderr` files instead
+
+mod common;
+
+use std::{fmt, fs, path::Path, process::ExitCode};
+
+use common::{Config, TempDir};
+use serde_json::Value;
+
+fn main() -> ExitCode {
+    let config = Config::from_args();
+    let ui = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ui");
+    let names: Vec<String> = common::entries(&ui, |path| path.extension().is_some_and(|ext| ext == "crs"))
+        .iter()
+        .map(|path| path.file_stem().unwrap().to_string_lossy().into_owned())
+        .collect();
+    common::run(&config, "ui", &names, |name| run_test(&config, &ui, name))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Kind {
+    Error,
+    Warning,
+    Help,
+    Note,
+}
+
+impl Kind {
+    fn from_annotation(s: &str) -> Option<Self> {
+        match s {
+            "ERROR" => Some(Self::Error),
+            "WARN" | "WARNING" => Some(Self::Warning),
+            "HELP" => Some(Self::Help),
+            "NOTE" => Some(Self::Note),
+            _ => None,
+        }
+    }
+
+    fn from_level(s: &str) -> Option<Self> {
+        match s {
+            "error" => Some(Self::Error),
+            "warning" => Some(Self::Warning),
+            "help" => Some(Self::Help),
+            "note" => Some(Self::Note),
+            _ => None,
+        }
+    }
+}
+
+/// A diagnostic, either as annotated or as crustc reported it.
+struct Diagnostic {
+    /// 1-based, or `None` for diagnostics that aren't about any line.
+    line: Option<usize>,
+    kind: Kind,
+    /// What the annotation says, or the message and code crustc reported.
+    message: String,
+    code: Option<String>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {line}: ")?,
+            None => write!(f, "no line: ")?,
+        }
+        write!(f, "{:?}", self.kind)?;
+        if let Some(code) = &self.code {
+            write!(f, "[{code}]")?;
+        }
+        write!(f, " {}", self.message)
+    }
+}
+
+impl Diagnostic {
+    fn matches(&self, expected: &Diagnostic) -> bool {
+        self.line == expected.line
+            && self.kind == expected.kind
+            && (self.message.contains(&expected.message) || self.code.as_deref() == Some(expected.message.as_str()))
+    }
+}
+
+fn run_test(config: &Config, dir: &Path, name: &str) -> Result<(), String> {
+    let source_name = format!("{name}.crs");
+    let source = fs::read_to_string(dir.join(&source_name)).map_err(|err| format!("couldn't read {source_name}: {err}"))?;
+    let (expected, flags) = parse_annotations(&source)?;
+
+    // Build all the way to an object file, so that rustc's errors in the generated code are
+    // reported too.
+    let out_dir = TempDir::new("ui", name)?;
+    let object = out_dir.0.join(format!("{name}.o")).display().to_string();
+    let mut args = vec![source_name.as_str(), "--emit=obj", "-o", &object];
+    args.extend(flags.iter().map(String::as_str));
+
+    let human = common::crustc(dir, &args)?;
+    let stderr = String::from_utf8_lossy(&human.stderr);
+    let stderr_path = dir.join(format!("{name}.stderr"));
+    if !stderr.is_empty() {
+        common::check(config, &stderr_path, &stderr)?;
+    } else if config.bless {
+        let _ = fs::remove_file(&stderr_path);
+    } else if stderr_path.exists() {
+        return Err(format!("crustc printed nothing, but {} expects otherwise", stderr_path.display()));
+    }
+
+    let mut check_args = vec!["check", source_name.as_str()];
+    check_args.extend(flags.iter().map(String::as_str));
+    let check = common::crustc(dir, &check_args)?;
+    if check.stderr != human.stderr || check.status.code() != human.status.code() {
+        return Err(format!("`crustc check` reported something else:\n{}", String::from_utf8_lossy(&check.stderr)));
+    }
+
+    args.push("--error-format=json");
+    let json = common::crustc(dir, &args)?;
+    let actual = parse_json(&String::from_utf8_lossy(&json.stderr), &source_name)?;
+    compare(expected, actual)
+}
+
+/// The diagnostics `source` expects, and the extra flags it wants crustc run with.
+fn parse_annotations(source: &str) -> Result<(Vec<Diagnostic>, Vec<String>), String> {
+    let mut expected: Vec<Diagnostic> = vec![];
+    let mut flags = vec![];
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        if let Some(header) = line.trim_start().strip_prefix("//@") {
+            match header.trim().strip_prefix("compile-flags:") {
+                Some(value) => flags.extend(value.split_whitespace().map(str::to_string)),
+                None => return Err(format!("line {line_no}: unknown header `{}`", header.trim())),
+            }
+            continue;
+        }
+        let Some((_, annotation)) = line.split_once("//~") else {
+            continue;
+        };
+
+        let (target, rest) = if let Some(rest) = annotation.strip_prefix('|') {
+            let previous = expected.last().ok_or_else(|| format!("line {line_no}: `//~|` needs an annotation before it"))?;
+            (previous.line, rest)
+        } else if let Some(rest) = annotation.strip_prefix('?') {
+            (None, rest)
+        } else {
+            let carets = annotation.len() - annotation.trim_start_matches('^').len();
+            let target = line_no.checked_sub(carets).filter(|&target| target > 0);
+            let target = target.ok_or_else(|| format!("line {line_no}: `//~{}` points before the file", "^".repeat(carets)))?;
+            (Some(target), &annotation[carets..])
+        };
+
+        let rest = rest.trim();
+        let (kind, message) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let kind = Kind::from_annotation(kind)
+            .ok_or_else(|| format!("line {line_no}: expected ERROR, WARN, HELP or NOTE, found `{kind}`"))?;
+        expected.push(Diagnostic { line: target, kind, message: message.trim().to_string(), code: None });
+    }
+    Ok((expected, flags))
+}
+
+/// The diagnostics in crustc's `--error-format=json` output, children included.
+fn parse_json(stderr: &str, source_name: &str) -> Result<Vec<Diagnostic>, String> {
+    let mut diagnostics = vec![];
+    for line in stderr.lines() {
+        let value: Value = serde_json::from_str(line).map_err(|err| format!("crustc printed something that isn't JSON ({err}):\n{line}"))?;
+        let Some(kind) = value["level"].as_str().and_then(Kind::from_level) else {
+            // The notes after the summary.
+            continue;
+        };
+        let message = value["message"].as_str().unwrap_or_default();
+        if message.starts_with("aborting due to") {
+            continue;
+        }
+
+        let line = primary_line(&value, source_name);
+        let code = value["code"]["code"].as_str().map(str::to_string);
+        diagnostics.push(Diagnostic { line, kind, message: message.to_string(), code });
+        for child in value["children"].as_array().into_iter().flatten() {
+            let Some(kind) = child["level"].as_str().and_then(Kind::from_level) else {
+                continue;
+            };
+            // Children without a span of their own are about their parent's.
+            let child_line = primary_line(child, source_name).or(line);
+            let message = child["message"].as_str().unwrap_or_default().to_string();
+            diagnostics.push(Diagnostic { line: child_line, kind, message, code: None });
+        }
+    }
+    Ok(diagnostics)
+}
+
+fn primary_line(diagnostic: &Value, source_name: &str) -> Option<usize> {
+    diagnostic["spans"]
+        .as_array()?
+        .iter()
+        .find(|span| span["is_primary"].as_bool() == Some(true) && span["file_name"].as_str() == Some(source_name))
+        .and_then(|span| span["line_start"].as_u64())
+        .map(|line| line as usize)
+}
+
+/// Pairs up what was expected with what crustc reported, and complains about the rest.
+fn compare(expected: Vec<Diagnostic>, actual: Vec<Diagnostic>) -> Result<(), String> {
+    let checked_kinds: Vec<Kind> = [Kind::Error, Kind::Warning]
+        .into_iter()
+        .chain([Kind::Help, Kind::Note].into_iter().filter(|&kind| expected.iter().any(|diagnostic| diagnostic.kind == kind)))
+        .collect();
+
+    let mut unmatched: Vec<Option<Diagnostic>> = actual.into_iter().map(Some).collect();
+    let mut missing = vec![];
+    for expected in expected {
+        let found = unmatched
+            .iter_mut()
+            .find(|actual| actual.as_ref().is_some_and(|actual| actual.matches(&expected)));
+        match found {
+            Some(actual) => *actual = None,
+            None => missing.push(expected),
+        }
+    }
+    let unexpected: Vec<Diagnostic> = unmatched
+        .into_iter()
+        .flatten()
+        .filter(|actual| checked_kinds.contains(&actual.kind))
+        .collect();
+
+    if missing.is_empty() && unexpected.is_empty() {
+        return Ok(());
+    }
+    let mut why = String::new();
+    if !unexpected.is_empty() {
+        why.push_str("reported, but not annotated:\n");
+        for diagnostic in &unexpected {
+            why.push_str(&format!("    {diagnostic}\n"));
+        }
+    }
+    if !missing.is_empty() {
+        why.push_str("annotated, but not reported:\n");
+        for diagnostic in &missing {
+            why.push_str(&format!("    {diagnostic}\n"));
+        }
+    }
+    Err(why)
+}
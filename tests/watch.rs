@@ -0,0 +1,44 @@
+//! Noticing changes to the source the way `crustc --watch` does.
+
+// Like anything linking crustc, this has to link the compiler it's built on.
+#![feature(rustc_private)]
+
+extern crate rustc_driver;
+
+mod common;
+
+use std::{
+    fs::{self, File},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use common::TempDir;
+use crustc::session::Watcher;
+
+/// Writes `path` and dates it `seconds` from now, so that changes don't depend on how
+/// finely the filesystem keeps time.
+fn write(path: &Path, contents: &str, seconds: u64) {
+    fs::write(path, contents).unwrap();
+    File::options().write(true).open(path).unwrap().set_modified(SystemTime::now() + Duration::from_secs(seconds)).unwrap();
+}
+
+#[test]
+fn sees_each_change_once() {
+    let dir = TempDir::new("watch", "main").unwrap();
+    let source = dir.0.join("main.crs");
+    write(&source, "fn main() {}\n", 0);
+
+    let mut watcher = Watcher::new(&source);
+    assert!(!watcher.changed());
+
+    write(&source, "fn main() {\n}\n", 10);
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+
+    // An editor replacing the file leaves it missing for a moment, which isn't a change yet.
+    fs::remove_file(&source).unwrap();
+    assert!(!watcher.changed());
+    write(&source, "fn main() {}\n", 20);
+    assert!(watcher.changed());
+}